@@ -0,0 +1,543 @@
+use super::client::Error;
+use super::packet::{CodecType, PacketError};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace};
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::timeout};
+use tokio_util::codec::{Decoder, Encoder};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A [BattlEye RCon](https://www.battleye.com/downloads/BERConProtocol.txt) packet.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BePacket {
+    /// Sent by the client to log in with the password.
+    Login(String),
+    /// The server's reply to `Login`, whether the password was accepted.
+    LoginResponse(bool),
+    /// A command sent by the client, an empty command is used as a keepalive.
+    Command {
+        /// The sequence number echoed back in the response.
+        seq: u8,
+        /// The command.
+        body: String,
+    },
+    /// The complete response to the command with the same sequence number.
+    CommandResponse {
+        /// The sequence number of the command.
+        seq: u8,
+        /// The response.
+        body: String,
+    },
+    /// One part of a response which was split over several packets.
+    CommandResponsePart {
+        /// The sequence number of the command.
+        seq: u8,
+        /// The number of parts.
+        count: u8,
+        /// The index of this part.
+        index: u8,
+        /// This part of the response.
+        body: String,
+    },
+    /// A message broadcast by the server, which the client must acknowledge.
+    ServerMessage {
+        /// The sequence number to acknowledge.
+        seq: u8,
+        /// The message.
+        body: String,
+    },
+    /// The client's acknowledgement of a `ServerMessage`.
+    MessageAck {
+        /// The sequence number of the message.
+        seq: u8,
+    },
+}
+
+const LOGIN: u8 = 0x00;
+const COMMAND: u8 = 0x01;
+const MESSAGE: u8 = 0x02;
+/// `B`, `E`, 4 checksum bytes and the `0xFF` separator.
+const HEADER_LEN: usize = 7;
+
+/// The IEEE CRC32 of `data`, which BattlEye uses to checksum packets.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// A codec for BattlEye packets. Each packet is a whole UDP datagram, so `decode` expects one datagram at a time.
+/// Like [`PacketCodec`](super::packet::PacketCodec), the side decoding decides how packet types are read.
+pub struct BattlEyeCodec {
+    ctype: CodecType,
+}
+
+impl BattlEyeCodec {
+    /// Creates a codec for the given side of the connection.
+    pub fn new(ctype: CodecType) -> Self {
+        BattlEyeCodec { ctype }
+    }
+}
+
+impl Encoder<BePacket> for BattlEyeCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: BePacket, dst: &mut BytesMut) -> io::Result<()> {
+        let mut payload = BytesMut::new();
+        payload.put_u8(0xFF);
+        match item {
+            BePacket::Login(password) => {
+                payload.put_u8(LOGIN);
+                payload.put_slice(password.as_bytes());
+            }
+            BePacket::LoginResponse(ok) => {
+                payload.put_u8(LOGIN);
+                payload.put_u8(ok as u8);
+            }
+            BePacket::Command { seq, body } | BePacket::CommandResponse { seq, body } => {
+                payload.put_u8(COMMAND);
+                payload.put_u8(seq);
+                payload.put_slice(body.as_bytes());
+            }
+            BePacket::CommandResponsePart {
+                seq,
+                count,
+                index,
+                body,
+            } => {
+                payload.put_u8(COMMAND);
+                payload.put_slice(&[seq, 0, count, index]);
+                payload.put_slice(body.as_bytes());
+            }
+            BePacket::ServerMessage { seq, body } => {
+                payload.put_u8(MESSAGE);
+                payload.put_u8(seq);
+                payload.put_slice(body.as_bytes());
+            }
+            BePacket::MessageAck { seq } => {
+                payload.put_u8(MESSAGE);
+                payload.put_u8(seq);
+            }
+        }
+        dst.reserve(6 + payload.len());
+        dst.put_slice(b"BE");
+        dst.put_u32_le(crc32(&payload));
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+impl Decoder for BattlEyeCodec {
+    type Item = BePacket;
+    type Error = PacketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::result::Result<Option<BePacket>, PacketError> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let datagram = src.split();
+        if datagram.len() < HEADER_LEN + 1 {
+            return Err(PacketError::InvalidLength);
+        }
+        if &datagram[..2] != b"BE" || datagram[6] != 0xFF {
+            return Err(PacketError::InvalidHeader);
+        }
+        let checksum = (&datagram[2..6]).get_u32_le();
+        if checksum != crc32(&datagram[6..]) {
+            return Err(PacketError::InvalidChecksum);
+        }
+
+        let ptype = datagram[HEADER_LEN];
+        let rest = &datagram[HEADER_LEN + 1..];
+        let text = |b: &[u8]| String::from_utf8_lossy(b).into_owned();
+        let p = match (ptype, self.ctype, rest) {
+            (LOGIN, CodecType::Server, password) => BePacket::Login(text(password)),
+            (LOGIN, CodecType::Client, [ok]) => BePacket::LoginResponse(*ok == 1),
+            (COMMAND, CodecType::Server, [seq, body @ ..]) => BePacket::Command {
+                seq: *seq,
+                body: text(body),
+            },
+            // a response split into no parts can never be completed
+            (COMMAND, CodecType::Client, [_, 0, 0, ..]) => return Err(PacketError::InvalidLength),
+            (COMMAND, CodecType::Client, [seq, 0, count, index, body @ ..]) => {
+                BePacket::CommandResponsePart {
+                    seq: *seq,
+                    count: *count,
+                    index: *index,
+                    body: text(body),
+                }
+            }
+            (COMMAND, CodecType::Client, [seq, body @ ..]) => BePacket::CommandResponse {
+                seq: *seq,
+                body: text(body),
+            },
+            (MESSAGE, CodecType::Server, [seq]) => BePacket::MessageAck { seq: *seq },
+            (MESSAGE, CodecType::Client, [seq, body @ ..]) => BePacket::ServerMessage {
+                seq: *seq,
+                body: text(body),
+            },
+            (LOGIN | COMMAND | MESSAGE, _, _) => return Err(PacketError::InvalidLength),
+            _ => return Err(PacketError::UndefinedType),
+        };
+        Ok(Some(p))
+    }
+}
+
+/// A client's UDP session with a BattlEye server.
+/// UDP can drop packets, so every request is sent up to `attempts` times, waiting `wait` for a reply each time.
+pub(crate) struct Session {
+    socket: UdpSocket,
+    codec: BattlEyeCodec,
+    seq: u8,
+    wait: Duration,
+    attempts: u32,
+    buf: Vec<u8>,
+}
+
+impl Session {
+    pub(crate) async fn connect(
+        addr: SocketAddr,
+        wait: Duration,
+        attempts: u32,
+    ) -> io::Result<Session> {
+        let local: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok(Session {
+            socket,
+            codec: BattlEyeCodec::new(CodecType::Client),
+            seq: 0,
+            wait,
+            attempts: attempts.max(1),
+            buf: vec![0; u16::MAX as usize],
+        })
+    }
+
+    /// Logs in, returning whether the password was accepted.
+    pub(crate) async fn login(&mut self, password: &str) -> Result<bool> {
+        for _ in 0..self.attempts {
+            self.send(BePacket::Login(password.to_owned())).await?;
+            let wait = self.wait;
+            let res = timeout(wait, async {
+                loop {
+                    if let BePacket::LoginResponse(ok) = self.recv().await? {
+                        return Ok(ok);
+                    }
+                }
+            });
+            match res.await {
+                Ok(r) => return r,
+                Err(_) => trace!("no login response, resending"),
+            }
+        }
+        Err(timed_out())
+    }
+
+    /// Runs a command, returning its sequence number and the response.
+    pub(crate) async fn cmd(&mut self, body: &str) -> Result<(u8, String)> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        for _ in 0..self.attempts {
+            self.send(BePacket::Command {
+                seq,
+                body: body.to_owned(),
+            })
+            .await?;
+            let wait = self.wait;
+            match timeout(wait, self.read_response(seq)).await {
+                Ok(r) => return r.map(|body| (seq, body)),
+                Err(_) => trace!("no response to command {}, resending", seq),
+            }
+        }
+        Err(timed_out())
+    }
+
+    /// Reads until the response to `seq` is complete, acknowledging server messages along the way.
+    async fn read_response(&mut self, seq: u8) -> Result<String> {
+        let mut parts: Vec<Option<String>> = Vec::new();
+        loop {
+            match self.recv().await? {
+                BePacket::CommandResponse { seq: s, body } if s == seq => return Ok(body),
+                BePacket::CommandResponsePart {
+                    seq: s,
+                    count,
+                    index,
+                    body,
+                } if s == seq => {
+                    if parts.len() != count as usize {
+                        parts = vec![None; count as usize];
+                    }
+                    if let Some(part) = parts.get_mut(index as usize) {
+                        *part = Some(body);
+                    }
+                    if parts.iter().all(Option::is_some) {
+                        return Ok(parts.into_iter().flatten().collect());
+                    }
+                }
+                BePacket::ServerMessage { seq: s, body } => {
+                    debug!("server message: {}", body);
+                    self.send(BePacket::MessageAck { seq: s }).await?;
+                }
+                p => trace!("ignoring packet {:?}", p),
+            }
+        }
+    }
+
+    async fn send(&mut self, p: BePacket) -> io::Result<()> {
+        let mut buf = BytesMut::new();
+        self.codec.encode(p, &mut buf)?;
+        self.socket.send(&buf).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<BePacket> {
+        let n = self.socket.recv(&mut self.buf).await?;
+        let mut datagram = BytesMut::from(&self.buf[..n]);
+        self.codec
+            .decode(&mut datagram)?
+            .ok_or(Error::Packet(PacketError::InvalidLength))
+    }
+}
+
+fn timed_out() -> Error {
+    Error::Io(io::Error::new(
+        ErrorKind::TimedOut,
+        "no response from the BattlEye server",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Connection, Protocol};
+
+    // built by hand following the BattlEye RCon protocol description
+    static LOGIN_PACKET: &[u8] = &[
+        0x42, 0x45, 0xde, 0x26, 0x2d, 0x52, 0xff, 0x00, 0x70, 0x61, 0x73, 0x73, 0x77, 0x6f, 0x72,
+        0x64,
+    ];
+    static LOGIN_OK: &[u8] = &[0x42, 0x45, 0x69, 0xdd, 0xde, 0x36, 0xff, 0x00, 0x01];
+    static RESPONSE: &[u8] = &[
+        0x42, 0x45, 0xf9, 0x37, 0x94, 0xae, 0xff, 0x01, 0x00, 0x70, 0x6c, 0x61, 0x79, 0x65, 0x72,
+        0x73,
+    ];
+    static RESPONSE_PART: &[u8] = &[
+        0x42, 0x45, 0xe5, 0x28, 0x7f, 0x74, 0xff, 0x01, 0x05, 0x00, 0x02, 0x01, 0x77, 0x6f, 0x72,
+        0x6c, 0x64,
+    ];
+    static MESSAGE_PACKET: &[u8] = &[
+        0x42, 0x45, 0x85, 0xa9, 0xc1, 0x39, 0xff, 0x02, 0x07, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+    ];
+
+    fn decode(ctype: CodecType, bytes: &[u8]) -> std::result::Result<BePacket, PacketError> {
+        BattlEyeCodec::new(ctype)
+            .decode(&mut BytesMut::from(bytes))
+            .map(Option::unwrap)
+    }
+
+    fn encode(p: BePacket) -> BytesMut {
+        let mut buf = BytesMut::new();
+        BattlEyeCodec::new(CodecType::Client)
+            .encode(p, &mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn encodes_login() {
+        assert_eq!(
+            &encode(BePacket::Login("password".into()))[..],
+            LOGIN_PACKET
+        );
+    }
+
+    #[test]
+    fn decodes_server_packets() {
+        assert_eq!(
+            decode(CodecType::Client, LOGIN_OK).unwrap(),
+            BePacket::LoginResponse(true)
+        );
+        assert_eq!(
+            decode(CodecType::Client, RESPONSE).unwrap(),
+            BePacket::CommandResponse {
+                seq: 0,
+                body: "players".into()
+            }
+        );
+        assert_eq!(
+            decode(CodecType::Client, RESPONSE_PART).unwrap(),
+            BePacket::CommandResponsePart {
+                seq: 5,
+                count: 2,
+                index: 1,
+                body: "world".into()
+            }
+        );
+        assert_eq!(
+            decode(CodecType::Client, MESSAGE_PACKET).unwrap(),
+            BePacket::ServerMessage {
+                seq: 7,
+                body: "hello".into()
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_client_packets() {
+        assert_eq!(
+            decode(CodecType::Server, LOGIN_PACKET).unwrap(),
+            BePacket::Login("password".into())
+        );
+    }
+
+    #[test]
+    fn rejects_responses_split_into_no_parts() {
+        let bytes = encode(BePacket::CommandResponsePart {
+            seq: 5,
+            count: 0,
+            index: 0,
+            body: "world".into(),
+        });
+        assert!(matches!(
+            decode(CodecType::Client, &bytes),
+            Err(PacketError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let mut bytes = LOGIN_OK.to_vec();
+        bytes[8] = 0x00;
+        assert!(matches!(
+            decode(CodecType::Client, &bytes),
+            Err(PacketError::InvalidChecksum)
+        ));
+        assert!(matches!(
+            decode(CodecType::Client, b"XX\0\0\0\0\xff\0\x01"),
+            Err(PacketError::InvalidHeader)
+        ));
+    }
+
+    #[tokio::test]
+    async fn cmd_runs_over_battleye() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut codec = BattlEyeCodec::new(CodecType::Server);
+            let mut buf = vec![0; 1024];
+            let mut reply = |p: BePacket| {
+                let mut out = BytesMut::new();
+                codec.encode(p, &mut out).unwrap();
+                out
+            };
+            let read = |n: usize, buf: &[u8]| {
+                BattlEyeCodec::new(CodecType::Server)
+                    .decode(&mut BytesMut::from(&buf[..n]))
+                    .unwrap()
+                    .unwrap()
+            };
+
+            let (n, peer) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(read(n, &buf), BePacket::Login("password".into()));
+            let ok = reply(BePacket::LoginResponse(true));
+            server.send_to(&ok, peer).await.unwrap();
+
+            let (n, _) = server.recv_from(&mut buf).await.unwrap();
+            let seq = match read(n, &buf) {
+                BePacket::Command { seq, body } if body == "players" => seq,
+                p => panic!("unexpected packet {:?}", p),
+            };
+            // a broadcast arrives first, then the response in two parts, out of order
+            let packets = [
+                BePacket::ServerMessage {
+                    seq: 0,
+                    body: "RCon admin logged in".into(),
+                },
+                BePacket::CommandResponsePart {
+                    seq,
+                    count: 2,
+                    index: 1,
+                    body: "world".into(),
+                },
+                BePacket::CommandResponsePart {
+                    seq,
+                    count: 2,
+                    index: 0,
+                    body: "hello ".into(),
+                },
+            ];
+            for p in packets {
+                server.send_to(&reply(p), peer).await.unwrap();
+            }
+            let (n, _) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(read(n, &buf), BePacket::MessageAck { seq: 0 });
+        });
+
+        let mut c = Connection::builder()
+            .protocol(Protocol::BattlEye)
+            .retry_delay(Duration::from_secs(5))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("players").await.unwrap(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn ping_keeps_the_session_alive() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut codec = BattlEyeCodec::new(CodecType::Server);
+            let mut buf = vec![0; 1024];
+            let mut logins = 0;
+            for _ in 0..3 {
+                let (n, peer) = server.recv_from(&mut buf).await.unwrap();
+                let reply = match codec.decode(&mut BytesMut::from(&buf[..n])).unwrap() {
+                    Some(BePacket::Login(_)) => {
+                        logins += 1;
+                        BePacket::LoginResponse(true)
+                    }
+                    Some(BePacket::Command { seq, body }) if body.is_empty() => {
+                        BePacket::CommandResponse { seq, body }
+                    }
+                    p => panic!("unexpected packet {:?}", p),
+                };
+                let mut out = BytesMut::new();
+                codec.encode(reply, &mut out).unwrap();
+                server.send_to(&out, peer).await.unwrap();
+            }
+            logins
+        });
+
+        let mut c = Connection::builder()
+            .protocol(Protocol::BattlEye)
+            .retry_delay(Duration::from_secs(5))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.ping().await.unwrap();
+        c.ping().await.unwrap();
+        assert_eq!(server.await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,116 @@
+use super::client::{self, Builder, Error};
+use std::result;
+use tokio::{
+    net::ToSocketAddrs,
+    runtime::{self, Runtime},
+};
+
+type Result<T> = result::Result<T, Error>;
+
+/// A blocking wrapper around [`client::Connection`] which drives it on its own current-thread runtime.
+///
+/// WARNING: Don't use it from async code, starting or dropping its runtime inside another runtime panics.
+/// Async code should use [`client::Connection`] directly.
+/// ```no_run
+/// fn main() -> Result<(), rcon_rs::client::Error> {
+///     let mut c = rcon_rs::blocking::Connection::connect("127.0.0.1:25575", "password")?;
+///     println!("{}", c.cmd("list")?);
+///     c.close()
+/// }
+/// ```
+pub struct Connection {
+    runtime: Runtime,
+    inner: client::Connection,
+}
+
+impl Connection {
+    /// Connects to the server with the default options.
+    pub fn connect<S: ToSocketAddrs, P: ToString>(addr: S, password: P) -> Result<Connection> {
+        Self::connect_with(client::Connection::builder(), addr, password)
+    }
+
+    /// Connects to the server with the options set on an async `Builder`, whose setters don't need a runtime.
+    pub fn connect_with<S: ToSocketAddrs, P: ToString>(
+        builder: Builder,
+        addr: S,
+        password: P,
+    ) -> Result<Connection> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let inner = runtime.block_on(builder.connect(addr, password))?;
+        Ok(Connection { runtime, inner })
+    }
+
+    /// Sends a command to the server and waits for the response, see [`client::Connection::cmd`].
+    pub fn cmd<C: ToString>(&mut self, cmd: C) -> Result<String> {
+        self.runtime.block_on(self.inner.cmd(cmd))
+    }
+
+    /// Whether the connection is open and authenticated.
+    pub fn is_healthy(&self) -> bool {
+        self.inner.is_healthy()
+    }
+
+    /// Shuts down the connection.
+    pub fn close(self) -> Result<()> {
+        self.runtime.block_on(self.inner.close())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{CodecType, Packet, PacketCodec, PacketType};
+    use futures::{SinkExt, StreamExt};
+    use std::{sync::mpsc, thread, time::Duration};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    /// Runs a server on its own thread which logs anyone in and echoes one command.
+    fn echo_server() -> std::net::SocketAddr {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let rt = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                tx.send(listener.local_addr().unwrap()).unwrap();
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut s = Framed::new(socket, PacketCodec::new(CodecType::Server, 4096));
+                for (ptype, body) in [
+                    (PacketType::AuthResponse, None),
+                    (PacketType::ResponseValue, Some("echo: ")),
+                ] {
+                    let p = s.next().await.unwrap().unwrap();
+                    s.send(Packet {
+                        ptype,
+                        id: p.id,
+                        body: body.map_or_else(String::new, |b| format!("{}{}", b, p.body)),
+                    })
+                    .await
+                    .unwrap();
+                }
+                // wait for the client to hang up
+                s.next().await;
+            });
+        });
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn runs_without_a_runtime() {
+        let addr = echo_server();
+        let mut c = Connection::connect_with(
+            client::Connection::builder().retry_delay(Duration::from_millis(1)),
+            addr,
+            "password",
+        )
+        .unwrap();
+        assert!(c.is_healthy());
+        assert_eq!(c.cmd("list").unwrap(), "echo: list");
+        c.close().unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+use env_logger::{Builder, Env, Target};
+use log::SetLoggerError;
+
+/// Installs an `env_logger` writing to stdout, with the filter read from the `LOG` environment variable.
+/// Fails if a logger is already installed.
+pub fn init_logger() -> Result<(), SetLoggerError> {
+    Builder::from_env(Env::default().filter("LOG"))
+        .target(Target::Stdout)
+        .try_init()
+}
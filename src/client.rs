@@ -1,261 +1,3941 @@
-use super::packet::{Packet, PacketCodec, PacketError, PacketType};
-use futures::{SinkExt, StreamExt};
-use log::*;
-use rand::{thread_rng, Rng};
+use super::battleye;
+use super::command::{self, Command, CommandError};
+use super::packet::{CodecType, Packet, PacketCodec, PacketError, PacketType, ERROR_PREFIX};
+use super::profile::ServerProfile;
+use super::transport::set_tcp_keepalive;
+pub use super::transport::Transport;
+use bytes::BytesMut;
+use futures::{
+    future::{BoxFuture, Either},
+    stream, FutureExt, SinkExt, StreamExt,
+};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace, warn};
+use rand::Rng;
+#[cfg(all(unix, feature = "unix"))]
+use std::path::{Path, PathBuf};
 use std::{
+    collections::VecDeque,
     error::Error as ErrorTrait,
     fmt::{self, Display, Formatter},
+    future::Future,
     io::Error as IoError,
     io::{self, ErrorKind},
     net::SocketAddr,
     result,
+    str::FromStr,
+    sync::Arc,
     time::Duration,
 };
+#[cfg(all(unix, feature = "unix"))]
+use tokio::net::UnixStream;
 use tokio::{
-    net::{lookup_host, TcpStream, ToSocketAddrs},
-    time::sleep,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs},
+    time::{sleep, Instant},
 };
 use tokio_util::codec::*;
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace, warn};
+
+mod config;
+mod handle;
+mod id_trace;
+mod ids;
+mod persistent;
+mod pool;
+mod response;
+mod simple;
+pub use config::ConnectionConfig;
+pub use handle::ConnectionHandle;
+use id_trace::IdTrace;
+#[cfg(feature = "debug-ids")]
+pub use id_trace::{IdEvent, IdTraceEntry};
+#[cfg(any(test, feature = "testing"))]
+pub use ids::FixedIdSource;
+pub use ids::{IdSource, RandomIdSource, SequentialIdSource};
+pub use persistent::{ConnectionState, PersistentConnection};
+pub use pool::{ConnectionPool, ConnectionPoolBuilder, PooledConnection};
+pub use response::{Response, DEFAULT_ERROR_HINTS};
+pub use simple::Client;
+
+/// Connects with the default builder settings, runs a single command and closes the connection.
+pub async fn run_once<S: ToSocketAddrs, P: ToString, C: ToString>(
+    addr: S,
+    password: P,
+    cmd: C,
+) -> Result<String> {
+    let mut c = Connection::builder().connect(addr, password).await?;
+    let response = c.cmd(cmd).await?;
+    c.close().await?;
+    Ok(response)
+}
+
+type Stream = Framed<Box<dyn Transport>, PacketCodec>;
+
+/// Where a connection is opened to.
+#[derive(Clone, Debug)]
+enum Endpoint {
+    /// A tcp address, with the host name it was looked up from when there is one to look up again on
+    /// reconnecting, and every address the last lookup returned
+    Host {
+        host: Option<String>,
+        addr: SocketAddr,
+        addrs: Vec<SocketAddr>,
+    },
+    #[cfg(all(unix, feature = "unix"))]
+    Unix(PathBuf),
+    /// A transport passed to `Builder::connect_io`, which can't be reopened
+    Io,
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Host {
+                host: Some(host),
+                addr,
+                ..
+            } if *host != addr.to_string() => write!(f, "{} ({})", host, addr),
+            Endpoint::Host { addr, .. } => write!(f, "{}", addr),
+            #[cfg(all(unix, feature = "unix"))]
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+            Endpoint::Io => write!(f, "custom transport"),
+        }
+    }
+}
 
 /// A [RCON](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol) Connection.
 /// Automatic retries to connect to the server before returning an error.
 pub struct Connection {
-    stream: Option<Framed<TcpStream, PacketCodec>>,
-    host: SocketAddr,
+    stream: Option<Stream>,
+    battleye: Option<battleye::Session>,
+    id_trace: IdTrace,
+    host: Endpoint,
     password: String,
     authenticated: bool,
+    needs_reauth: bool,
+    /// Other endpoints and their passwords to fail over to, in the order they are tried
+    fallbacks: VecDeque<(Endpoint, String)>,
     max_retries: u32,
     retry_delay: Duration,
     exponential_backoff: bool,
+    structured_errors: bool,
+    slow_command_threshold: Option<Duration>,
+    max_packet_length: usize,
+    bind_address: Option<SocketAddr>,
+    tcp_nodelay: bool,
+    keepalive_interval: Option<Duration>,
+    auto_reconnect: bool,
+    command_timeout: Option<Duration>,
+    max_pending_requests: Option<usize>,
+    backpressure: Backpressure,
+    pipeline_auth: bool,
+    protocol: Protocol,
+    jitter: f64,
+    auth_skip_packets: usize,
+    packet_observer: Option<PacketObserver>,
+    resolver: Resolver,
+    max_retry_delay: Duration,
+    round_robin_dns: bool,
+    auth_max_retries: u32,
+    auth_id: Option<i32>,
+    server_kind: ServerKind,
+    error_hints: Vec<String>,
+    dns_ttl: Duration,
+    /// When the host was last resolved
+    resolved_at: Instant,
+    tcp_keepalive: Option<Duration>,
+    id_generator: IdGenerator,
+    profile: ServerProfile,
+    #[cfg(feature = "proxy")]
+    proxy: Option<ProxyConfig>,
+    /// The last tcp stream `open_stream` opened, for tests to check its socket options
+    #[cfg(test)]
+    opened: std::sync::Mutex<Option<OpenedSocket>>,
+}
+
+/// What tests check about a tcp stream once it has been opened.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+struct OpenedSocket {
+    local_addr: SocketAddr,
+    nodelay: bool,
+}
+
+/// Produces packet ids.
+type IdGenerator = Arc<dyn IdSource>;
+
+/// Which way a packet passed to a [`Builder::packet_observer`] was going.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// Written to the server.
+    Sent,
+    /// Read from the server.
+    Received,
+}
+
+/// Sees every packet sent and received.
+type PacketObserver = Arc<dyn Fn(Direction, &Packet) + Send + Sync>;
+
+fn observe(observer: &Option<PacketObserver>, direction: Direction, p: &Packet) {
+    if let Some(o) = observer {
+        o(direction, p);
+    }
+}
+
+/// Resolves a host name to its addresses.
+type Resolver =
+    Arc<dyn Fn(String) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> + Send + Sync>;
+
+/// The default resolver, using the system's DNS settings through `lookup_host`.
+fn system_resolver() -> Resolver {
+    Arc::new(|host| async move { Ok(lookup_host(host).await?.collect()) }.boxed())
+}
+
+/// Resolves the host name `host` with `resolver`, failing if it has no addresses.
+async fn resolve(resolver: &Resolver, host: String) -> io::Result<Endpoint> {
+    let addrs = resolver(host.clone()).await?;
+    Endpoint::tcp(Some(host), addrs)
+}
+
+/// Looks `addr` up once with the system's DNS settings, failing if it has no addresses.
+async fn lookup<S: ToSocketAddrs>(addr: S) -> io::Result<Endpoint> {
+    Endpoint::tcp(None, lookup_host(addr).await?.collect())
+}
+
+impl Endpoint {
+    fn tcp(host: Option<String>, addrs: Vec<SocketAddr>) -> io::Result<Endpoint> {
+        let addr = *addrs
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unable to resolve host"))?;
+        Ok(Endpoint::Host { host, addr, addrs })
+    }
 }
 
 /// A builder for the connection struct.
 pub struct Builder {
-    max_retries: u32,
-    retry_delay: Duration,
-    exponential_backoff: bool,
+    config: ConnectionConfig,
+    packet_observer: Option<PacketObserver>,
+    resolver: Option<Resolver>,
+    id_generator: Option<IdGenerator>,
+    profile: ServerProfile,
+    #[cfg(feature = "proxy")]
+    proxy: Option<ProxyConfig>,
+}
+
+/// What a [`ConnectionHandle`] does with a command when `max_pending_requests` are already waiting.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backpressure {
+    /// Fail the command with `Error::Busy`.
+    #[default]
+    Fail,
+    /// Wait for a pending request to finish, up to `command_timeout`.
+    Wait,
+}
+
+/// The rcon protocol spoken by the server.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Protocol {
+    /// The [Source RCON protocol](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol) over tcp.
+    #[default]
+    Source,
+    /// [BattlEye RCon](https://www.battleye.com/downloads/BERConProtocol.txt) over udp, used by Arma and DayZ.
+    /// Since udp packets can be lost, each request is sent up to `max_retries` times, waiting `retry_delay`
+    /// for a response each time. Handles, proxies, Unix sockets and custom transports aren't supported.
+    BattlEye,
+}
+
+/// The quirks of the server's Source RCON implementation the client allows for, see [`Builder::server_kind`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServerKind {
+    /// Allows for up to [`auth_skip_packets`](Builder::auth_skip_packets) packets before the auth response and
+    /// takes the first packet after a command as its response.
+    #[default]
+    Generic,
+    /// Expects the spec's single empty `ResponseValue` before the auth response.
+    Source,
+    /// Doesn't expect any packet before the auth response, and skips packets which don't answer the command,
+    /// like the empty packet some Minecraft servers send after logging in, instead of taking them as its response.
+    Minecraft,
+}
+
+impl ServerKind {
+    /// How many packets may come before the auth response.
+    fn auth_skip_packets(self, configured: usize) -> usize {
+        match self {
+            ServerKind::Generic => configured,
+            ServerKind::Source => 1,
+            ServerKind::Minecraft => 0,
+        }
+    }
+}
+
+/// A SOCKS5 proxy to route the connection through.
+#[cfg(feature = "proxy")]
+#[derive(Clone)]
+pub struct ProxyConfig {
+    addr: SocketAddr,
+    credentials: Option<(String, String)>,
+}
+
+#[cfg(feature = "proxy")]
+impl ProxyConfig {
+    /// Creates a config for an unauthenticated SOCKS5 proxy at `addr`.
+    pub fn new(addr: SocketAddr) -> Self {
+        ProxyConfig {
+            addr,
+            credentials: None,
+        }
+    }
+
+    /// Sets the username and password used to authenticate with the proxy.
+    pub fn credentials<U: ToString, P: ToString>(mut self, username: U, password: P) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
 }
 
 impl Builder {
     /// Completes the builder and connects to the rcon server using the specified options by the builder.
     /// Eargerly connects to the server.
+    ///
+    /// `addr` is looked up once and every address it resolves to is kept, so reconnects never look it up
+    /// again. A `ToSocketAddrs` can only be resolved, not turned back into the host name, so a server which
+    /// moves to a new address, like after a cloud failover, isn't found. Use [`connect_host`](Self::connect_host)
+    /// with the host name as a string for connections which should resolve it again when reconnecting.
     pub async fn connect<S: ToSocketAddrs, P: ToString>(
         self,
         addr: S,
         password: P,
     ) -> Result<Connection> {
-        let addr = match lookup_host(addr).await?.next() {
-            Some(s) => s,
-            None => {
-                return Err(Error::Io(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "unable to resolve host",
-                )))
+        let host = lookup(addr).await?;
+
+        trace!("connecting to {}", &host);
+
+        let mut c = self.build(host, password)?;
+        c.open().await?;
+        Ok(c)
+    }
+
+    /// Like [`connect`](Self::connect), but keeps the host name, such as `"rcon.example:25575"`, and
+    /// resolves it again if a reconnect attempt to the address it last resolved to fails, so a server
+    /// which moved to a new address is still found. The first attempt of each reconnect reuses the cached
+    /// address, and the host is resolved with the [`resolver`](Self::resolver) if one is set.
+    pub async fn connect_host<H: Into<String>, P: ToString>(
+        self,
+        host: H,
+        password: P,
+    ) -> Result<Connection> {
+        let resolver = self.resolver.clone().unwrap_or_else(system_resolver);
+        let host = resolve(&resolver, host.into()).await?;
+
+        trace!("connecting to {}", &host);
+
+        let mut c = self.build(host, password)?;
+        c.open().await?;
+        Ok(c)
+    }
+
+    /// Like [`connect`](Self::connect), but only resolves `addr`. The connection is opened and logged in
+    /// by the first command, so it can be created before the server is up.
+    pub async fn lazy<S: ToSocketAddrs, P: ToString>(
+        self,
+        addr: S,
+        password: P,
+    ) -> Result<Connection> {
+        let host = lookup(addr).await?;
+        self.build(host, password)
+    }
+
+    /// Completes the builder and connects with the password read from the environment variable `env_var`,
+    /// failing with `Error::MissingEnv` if it isn't set or isn't valid unicode.
+    pub async fn connect_env<S: ToSocketAddrs>(self, addr: S, env_var: &str) -> Result<Connection> {
+        let password = std::env::var(env_var).map_err(|_| Error::MissingEnv(env_var.to_owned()))?;
+        trace!("read the password from {}", env_var);
+        self.connect(addr, password).await
+    }
+
+    /// Completes the builder and connects to the first reachable server in `endpoints`, each with its own password.
+    /// The connection sticks to that server, but if it stays down for all `max_retries` attempts of a reconnect
+    /// the next endpoint in the list is tried, wrapping around. Endpoints which can't be resolved are skipped.
+    pub async fn connect_any<S: ToSocketAddrs, P: ToString>(
+        self,
+        endpoints: Vec<(S, P)>,
+    ) -> Result<Connection> {
+        let mut resolved = VecDeque::new();
+        for (addr, password) in endpoints {
+            match lookup(addr).await {
+                Ok(host) => resolved.push_back((host, password.to_string())),
+                Err(e) => warn!("skipping endpoint which failed to resolve: {}", e),
             }
-        };
+        }
+        let (host, password) = resolved.pop_front().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "unable to resolve any endpoint")
+        })?;
+
+        trace!("connecting to {} with {} fallbacks", host, resolved.len());
+        let mut c = self.build(host, password)?;
+        c.fallbacks = resolved;
+        c.open().await?;
+        Ok(c)
+    }
+
+    /// Completes the builder and connects to a rcon server listening on a Unix domain socket at `path`.
+    #[cfg(all(unix, feature = "unix"))]
+    pub async fn connect_unix<A: AsRef<Path>, P: ToString>(
+        self,
+        path: A,
+        password: P,
+    ) -> Result<Connection> {
+        trace!("connecting to {}", path.as_ref().display());
+        let mut c = self.build(Endpoint::Unix(path.as_ref().to_owned()), password)?;
+        c.open().await?;
+        Ok(c)
+    }
+
+    /// Completes the builder and logs in over an already open transport, such as a TLS stream or `tokio::io::duplex`.
+    /// The connection can't be reopened, so commands fail once the transport is closed.
+    pub async fn connect_io<T: Transport + 'static, P: ToString>(
+        self,
+        io: T,
+        password: P,
+    ) -> Result<Connection> {
+        let mut c = self.build(Endpoint::Io, password)?;
+        c.stream = Some(Framed::new(
+            Box::new(io),
+            PacketCodec::new(CodecType::Client, c.max_packet_length),
+        ));
+        if !c.pipeline_auth {
+            c.login().await?;
+        }
+        Ok(c)
+    }
+
+    /// Checks the settings `build` rejects, without consuming the builder so it can be kept when they fail.
+    fn validate(&self, password: &str) -> Result<()> {
+        if password.is_empty() && !self.config.allow_empty_password {
+            warn!("refusing to connect with an empty password, see Builder::allow_empty_password");
+            return Err(Error::EmptyPassword);
+        }
+        if self.config.validate_password && password.trim().is_empty() {
+            warn!("refusing to connect with an empty or whitespace-only password");
+            return Err(Error::BlankPassword);
+        }
+        if self.config.auth_id == Some(-1) {
+            return Err(Error::InvalidAuthId);
+        }
+        Ok(())
+    }
 
-        trace!("connecting to {}", &addr);
+    fn build<P: ToString>(self, host: Endpoint, password: P) -> Result<Connection> {
+        let password = password.to_string();
+        self.validate(&password)?;
 
-        let mut c = Connection {
+        let config = self.config;
+        Ok(Connection {
             stream: None,
-            host: addr,
-            password: password.to_string(),
+            battleye: None,
+            id_trace: IdTrace::default(),
+            host,
+            password,
             authenticated: false,
-            max_retries: self.max_retries,
-            retry_delay: self.retry_delay,
-            exponential_backoff: self.exponential_backoff,
-        };
+            needs_reauth: false,
+            fallbacks: VecDeque::new(),
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay,
+            exponential_backoff: config.exponential_backoff,
+            structured_errors: config.structured_errors,
+            slow_command_threshold: config.slow_command_threshold,
+            max_packet_length: config.max_packet_length,
+            bind_address: config.bind_address,
+            tcp_nodelay: config.tcp_nodelay,
+            keepalive_interval: config.keepalive_interval,
+            auto_reconnect: config.auto_reconnect,
+            command_timeout: config.command_timeout,
+            max_pending_requests: config.max_pending_requests,
+            backpressure: config.backpressure,
+            pipeline_auth: config.pipeline_auth,
+            protocol: config.protocol,
+            jitter: config::clamp_jitter(config.jitter),
+            auth_skip_packets: config.auth_skip_packets,
+            packet_observer: self.packet_observer,
+            resolver: self.resolver.unwrap_or_else(system_resolver),
+            max_retry_delay: config.max_retry_delay,
+            round_robin_dns: config.round_robin_dns,
+            auth_max_retries: config.auth_max_retries,
+            auth_id: config.auth_id,
+            server_kind: config.server_kind,
+            error_hints: config.error_hints,
+            dns_ttl: config.dns_ttl,
+            resolved_at: Instant::now(),
+            tcp_keepalive: config.tcp_keepalive,
+            id_generator: self
+                .id_generator
+                .unwrap_or_else(|| Arc::new(SequentialIdSource::default())),
+            profile: self.profile,
+            #[cfg(feature = "proxy")]
+            proxy: self.proxy,
+            #[cfg(test)]
+            opened: Default::default(),
+        })
+    }
 
-        c.connect().await?;
-        c.login().await?;
-        Ok(c)
+    /// The plain settings set so far.
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
     }
 
     /// Sets the maximum number of retries that will be made when calling `Connection::run` before throwing an error.
     pub fn max_retries(mut self, retries: u32) -> Self {
-        self.max_retries = retries;
+        self.config.max_retries = retries;
+        self
+    }
+
+    /// Sets how many times logging in is retried, on a new connection, when it fails for a reason other than
+    /// a rejected password, like the server closing the connection while it is still starting up.
+    /// Waits between attempts like connection retries do. Defaults to 0.
+    pub fn auth_max_retries(mut self, retries: u32) -> Self {
+        self.config.auth_max_retries = retries;
+        self
+    }
+
+    /// Sends every login with the id `auth_id` instead of one from the id generator, for replaying captures.
+    /// It can't be `-1`, which the server answers with to reject a password, connecting fails with
+    /// `Error::InvalidAuthId` if it is. Defaults to `None`.
+    pub fn auth_id(mut self, auth_id: Option<i32>) -> Self {
+        self.config.auth_id = auth_id;
         self
     }
 
     /// Sets the delay between retries.
     pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
-        self.retry_delay = retry_delay;
+        self.config.retry_delay = retry_delay;
         self
     }
 
     /// Sets whether the exponential backoff will be used when trying to reconnect.
+    /// The delay starts at `retry_delay` and doubles after every failed attempt.
     pub fn exponential_backoff(mut self, exponential_backoff: bool) -> Self {
-        self.exponential_backoff = exponential_backoff;
+        self.config.exponential_backoff = exponential_backoff;
         self
     }
-}
 
-impl Connection {
-    /// Creates a `Builder` for `Connection`.
-    pub fn builder() -> Builder {
-        Builder {
-            max_retries: 3,
-            retry_delay: Duration::from_millis(1000),
-            exponential_backoff: false,
-        }
+    /// Caps the delay between connection attempts once exponential backoff and jitter are applied, defaults to 30 seconds.
+    pub fn max_retry_delay(mut self, max_retry_delay: Duration) -> Self {
+        self.config.max_retry_delay = max_retry_delay;
+        self
     }
 
-    /// Sends a command to the connected server.
-    pub async fn cmd<C: ToString>(&mut self, cmd: C) -> Result<String> {
-        debug!("running command: \"{}\"", &cmd.to_string());
-        let pk = Packet {
-            ptype: PacketType::ExecCommand,
-            id: thread_rng().gen::<i32>(),
-            body: cmd.to_string(),
-        };
-        let stream = if let Some(s) = self.stream.as_mut() {
-            s
-        } else {
-            trace!("reconnecting");
-            self.connect().await?;
-            self.login().await?;
-            self.stream.as_mut().unwrap()
-        };
+    /// Randomizes each delay between connection attempts by up to `fraction` of its length in either direction,
+    /// so many clients reconnecting at once don't retry in lockstep. `fraction` is clamped to `0.0..=1.0`,
+    /// disabled (`0.0`) by default.
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        self.config.jitter = config::clamp_jitter(fraction);
+        self
+    }
 
-        stream.send(pk).await?;
+    /// Sets whether `connect` rejects empty or whitespace-only passwords with `Error::BlankPassword` before connecting.
+    /// Disabled by default, empty passwords are rejected separately unless `allow_empty_password` is set.
+    pub fn validate_password(mut self, validate_password: bool) -> Self {
+        self.config.validate_password = validate_password;
+        self
+    }
 
-        let p = match stream.next().await {
-            Some(Ok(x)) => Ok(x),
-            Some(Err(e)) => Err(Error::from(e)),
-            None => Err(Error::Io(IoError::new(
-                ErrorKind::ConnectionAborted,
-                "Server ended the connection",
-            ))),
-        }?;
-        Ok(p.body)
+    /// Sets whether an empty password is sent to the server. By default `connect` fails with `Error::EmptyPassword`
+    /// instead, since most servers reject it without saying why. Enable this for servers which intentionally have no password.
+    pub fn allow_empty_password(mut self, allow_empty_password: bool) -> Self {
+        self.config.allow_empty_password = allow_empty_password;
+        self
     }
-}
 
-// private methods
-impl Connection {
-    async fn connect(&mut self) -> io::Result<()> {
-        for retries in 1..self.max_retries + 1 {
-            trace!("Attempting to connect to {} #{}", &self.host, retries);
-            let s = match TcpStream::connect(self.host).await {
-                Ok(s) => s,
-                Err(e) => {
-                    trace!("failed to connect to server: {}", e);
-                    sleep(if self.exponential_backoff {
-                        Duration::from_millis((self.retry_delay.as_millis() as u64).pow(retries))
-                    } else {
-                        self.retry_delay
-                    })
-                    .await;
-                    continue;
-                }
-            };
+    /// Sets whether responses starting with [`ERROR_PREFIX`] are returned as `Error::CommandRejected`.
+    /// Only enable this when connecting to a server built with this crate, other servers' bodies are returned untouched.
+    pub fn structured_errors(mut self, structured_errors: bool) -> Self {
+        self.config.structured_errors = structured_errors;
+        self
+    }
 
-            self.stream = Some(Framed::new(s, PacketCodec::new_client()));
+    /// Logs a warning whenever a command takes longer than `threshold` to get a response.
+    pub fn slow_command_threshold(mut self, threshold: Duration) -> Self {
+        self.config.slow_command_threshold = Some(threshold);
+        self
+    }
 
-            return Ok(());
-        }
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "unable to resolve host",
-        ))
+    /// Sets the maximum length of inbound packets, longer packets are skipped.
+    /// WARNING: The [RCON spec](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol#Packet_Size) sets a maximum packet size of 4096 bytes, only raise it for servers which send larger packets.
+    pub fn max_packet_length(mut self, max_packet_length: usize) -> Self {
+        self.config.max_packet_length = max_packet_length;
+        self
     }
 
-    async fn login(&mut self) -> Result<()> {
-        self.authenticated = false;
-        let aid = thread_rng().gen::<i32>();
+    /// Binds outbound connections to a local address, to choose which interface they are made from.
+    pub fn bind_address(mut self, addr: SocketAddr) -> Self {
+        self.config.bind_address = Some(addr);
+        self
+    }
 
-        let pk = Packet {
-            ptype: PacketType::Auth,
-            id: aid,
-            body: self.password.clone(),
-        };
+    /// Sets whether `TCP_NODELAY` is set on the connection, disabling Nagle's algorithm.
+    /// Enabled by default since rcon sends small request/response packets.
+    pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.config.tcp_nodelay = tcp_nodelay;
+        self
+    }
 
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not connected"))?;
+    /// Turns on TCP keepalive, so the OS notices a dead server after the connection has been idle for `time`
+    /// even when no commands are being sent. Disabled by default, see [`keepalive_interval`](Self::keepalive_interval)
+    /// for keepalive commands instead.
+    pub fn tcp_keepalive(mut self, time: Option<Duration>) -> Self {
+        self.config.tcp_keepalive = time;
+        self
+    }
 
-        stream.send(pk).await?;
+    /// Sets the function used to generate packet ids, which count up from 1 by default.
+    /// Ids are masked to be non-negative since the server uses `-1` to signal a failed login.
+    pub fn id_generator<F: Fn() -> i32 + Send + Sync + 'static>(self, id_generator: F) -> Self {
+        self.id_source(id_generator)
+    }
 
-        for _ in 0..2 {
-            match stream.next().await {
-                Some(Ok(p)) if p.ptype == PacketType::AuthResponse => {
-                    return if p.id == aid {
-                        trace!("client successfully logged in");
-                        self.authenticated = true;
-                        Ok(())
-                    } else {
-                        trace!("authentication failed");
-                        Err(Error::Io(IoError::new(
-                            io::ErrorKind::Other,
-                            "Incorrect password",
-                        )))
-                    };
-                }
-                Some(Ok(_)) => {
-                    trace!("client recieved non auth response when reading for auth response");
-                    continue;
-                }
-                Some(Err(e)) => {
-                    trace!("read error from buffer: {:?}", e);
-                    continue;
-                } // fix this
-                None => {
-                    trace!("stream ended while waiting for auth response");
-                    return Err(Error::Io(io::Error::new(
-                        io::ErrorKind::ConnectionAborted,
-                        "Connection to the server ",
-                    )));
-                }
-            }
-        }
-        Err(Error::InvalidResponse)
+    /// Sets where packet ids come from, such as a [`RandomIdSource`]. Defaults to a [`SequentialIdSource`].
+    pub fn id_source<S: IdSource + 'static>(mut self, source: S) -> Self {
+        self.id_generator = Some(Arc::new(source));
+        self
     }
-}
 
-type Result<T> = result::Result<T, Error>;
+    /// Calls `observer` with every packet sent and received, including by handles, for debugging protocol quirks.
+    /// WARNING: The body of the sent `Auth` packet is the password, so take care when logging what it sees.
+    pub fn packet_observer<F: Fn(Direction, &Packet) + Send + Sync + 'static>(
+        mut self,
+        observer: F,
+    ) -> Self {
+        self.packet_observer = Some(Arc::new(observer));
+        self
+    }
 
-/// Error type
-#[derive(Debug)]
-pub enum Error {
-    /// For io Errors
-    Io(io::Error),
-    /// Packet Error
-    PacketError,
-    /// Invalide Response
-    InvalidResponse,
-}
+    /// Resolves host names with `resolver` instead of the system's DNS settings, on connecting and again
+    /// whenever a reconnect attempt fails. It's called with the host as it was passed to
+    /// [`connect_host`](Self::connect_host), addresses passed to `connect` are looked up by the system.
+    pub fn resolver<F, Fut>(mut self, resolver: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<Vec<SocketAddr>>> + Send + 'static,
+    {
+        self.resolver = Some(Arc::new(move |host| resolver(host).boxed()));
+        self
+    }
 
-impl From<IoError> for Error {
-    fn from(err: IoError) -> Self {
-        Self::Io(err)
+    /// Sets how long a resolved address is reused before a failed reconnect attempt resolves the host
+    /// again, defaults to zero so every reconnect whose first attempt fails resolves it.
+    pub fn dns_ttl(mut self, ttl: Duration) -> Self {
+        self.config.dns_ttl = ttl;
+        self
     }
-}
 
-impl From<PacketError> for Error {
-    fn from(err: PacketError) -> Self {
-        match err {
-            PacketError::InvalidLength => Error::PacketError,
-            PacketError::UndefinedType => Error::PacketError,
-            PacketError::Io(e) => Error::Io(e),
-        }
+    /// Sets whether a host which resolves to several addresses is tried at each of them in turn when
+    /// reconnect attempts fail, rather than always at the first. Disabled by default.
+    pub fn round_robin_dns(mut self, enabled: bool) -> Self {
+        self.config.round_robin_dns = enabled;
+        self
     }
-}
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> result::Result<(), fmt::Error> {
-        match self {
-            Error::Io(e) => {
-                write!(f, "Io Error: {}", e)
-            }
-            Error::PacketError => {
-                write!(f, "Packet Error")
-            }
-            Error::InvalidResponse => {
-                write!(f, "Invalid Response")
-            }
-        }
+    /// Sets the server profile, which rewrites commands to suit the server. Defaults to `ServerProfile::generic()`.
+    pub fn profile(mut self, profile: ServerProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Sets how often an idle [`ConnectionHandle`] sends an empty command to stop NATs and firewalls
+    /// from timing out the connection. Pings are routed by id like any other command, so they never
+    /// get mixed up with responses to `cmd`. Disabled by default.
+    /// Only handles send keepalives, a plain `Connection` ignores this.
+    pub fn keepalive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.keepalive_interval = interval;
+        self
+    }
+
+    /// Sets whether a [`ConnectionHandle`] reconnects as soon as its connection drops.
+    /// Commands issued while the server is unreachable are held while reconnecting is retried up to
+    /// `max_retries` times with the usual backoff, a rejected password fails them straight away.
+    /// Commands that were already in flight when the connection dropped still fail with `Error::Disconnected`.
+    pub fn auto_reconnect(mut self, auto_reconnect: bool) -> Self {
+        self.config.auto_reconnect = auto_reconnect;
+        self
+    }
+
+    /// Sets how long a [`ConnectionHandle`] command may take, including any reconnect, before failing with a `TimedOut` io error.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.config.command_timeout = Some(timeout);
+        self
     }
-}
 
-impl ErrorTrait for Error {}
+    /// Limits how many commands a [`ConnectionHandle`] can have waiting on responses at once.
+    /// What happens to commands over the limit is set by `backpressure`. Unlimited by default.
+    pub fn max_pending_requests(mut self, max_pending_requests: usize) -> Self {
+        self.config.max_pending_requests = Some(max_pending_requests);
+        self
+    }
+
+    /// Sets what happens to commands over `max_pending_requests`, defaults to `Backpressure::Fail`.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.config.backpressure = backpressure;
+        self
+    }
+
+    /// Sets whether logging in is deferred to the first command, sending the auth packet and the command together
+    /// to save a round trip. If the password is rejected that command fails with `Error::AuthFailed` and its result is discarded.
+    /// WARNING: Servers which close the connection when they get a command before the auth response has been sent
+    /// will fail every pipelined login, only enable this for servers known to queue it.
+    pub fn pipeline_auth(mut self, pipeline_auth: bool) -> Self {
+        self.config.pipeline_auth = pipeline_auth;
+        self
+    }
+
+    /// Sets how many other packets may arrive while waiting for the auth response before logging in fails with
+    /// `Error::InvalidResponse`. Servers following the spec send one empty `ResponseValue` first, defaults to 3.
+    pub fn auth_skip_packets(mut self, auth_skip_packets: usize) -> Self {
+        self.config.auth_skip_packets = auth_skip_packets;
+        self
+    }
+
+    /// Sets the text which marks a response from [`Connection::cmd_response`] as a likely error when it
+    /// appears anywhere in the body, matching case. Defaults to [`DEFAULT_ERROR_HINTS`].
+    pub fn error_hints(mut self, hints: Vec<String>) -> Self {
+        self.config.error_hints = hints;
+        self
+    }
+
+    /// Sets which server's quirks to allow for, defaults to `ServerKind::Generic`.
+    /// This doesn't change how commands are rewritten, that is up to the [`profile`](Self::profile).
+    pub fn server_kind(mut self, kind: ServerKind) -> Self {
+        self.config.server_kind = kind;
+        self
+    }
+
+    /// Sets the protocol spoken by the server, defaults to `Protocol::Source`.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.config.protocol = protocol;
+        self
+    }
+
+    /// Routes the connection through a SOCKS5 proxy.
+    #[cfg(feature = "proxy")]
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+impl fmt::Debug for Builder {
+    /// Shows the settings, leaving out the proxy credentials.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("Builder");
+        d.field("config", &self.config);
+        #[cfg(feature = "proxy")]
+        d.field("proxy", &self.proxy.as_ref().map(|p| p.addr));
+        d.finish_non_exhaustive()
+    }
+}
+
+impl From<ConnectionConfig> for Builder {
+    /// Creates a builder with the given settings and the default id generator and profile.
+    fn from(config: ConnectionConfig) -> Self {
+        Builder {
+            config,
+            ..Connection::builder()
+        }
+    }
+}
+
+impl Connection {
+    /// Logs in over an already open transport with the default builder settings, see [`Builder::connect_io`].
+    pub async fn from_io<T: Transport + 'static, P: ToString>(
+        io: T,
+        password: P,
+    ) -> Result<Connection> {
+        Self::builder().connect_io(io, password).await
+    }
+
+    /// Connects to `addr` with saved settings, see [`ConnectionConfig`].
+    pub async fn from_config<S: ToSocketAddrs, P: ToString>(
+        config: ConnectionConfig,
+        addr: S,
+        password: P,
+    ) -> Result<Connection> {
+        Builder::from(config).connect(addr, password).await
+    }
+
+    /// Creates a `Builder` for `Connection`.
+    pub fn builder() -> Builder {
+        Builder {
+            config: ConnectionConfig::default(),
+            packet_observer: None,
+            resolver: None,
+            id_generator: None,
+            profile: ServerProfile::generic(),
+            #[cfg(feature = "proxy")]
+            proxy: None,
+        }
+    }
+
+    /// Sends a command to the connected server.
+    /// If the server drops the connection while the command is in flight, the connection is
+    /// re-established and the command is sent once more, so commands should be safe to repeat.
+    pub async fn cmd<C: ToString>(&mut self, cmd: C) -> Result<String> {
+        self.cmd_with_id(cmd).await.map(|(_, body)| body)
+    }
+
+    /// Like [`cmd`](Self::cmd), but also returns the id the server echoed back with the response,
+    /// for correlating responses with requests in logs or other tooling.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "cmd", skip_all, fields(host = %self.host, id = tracing::field::Empty))
+    )]
+    pub async fn cmd_with_id<C: ToString>(&mut self, cmd: C) -> Result<(i32, String)> {
+        let cmd = cmd.to_string();
+        let cmd = self.profile.normalize(&cmd).into_owned();
+        debug!("running command: \"{}\"", &cmd);
+        let start = Instant::now();
+        let p = match self.exec(&cmd).await? {
+            Some(p) => p,
+            // a custom transport can't be reopened to retry on
+            None if matches!(self.host, Endpoint::Io) => return Err(Error::ConnectionClosed),
+            None => {
+                trace!("connection dropped while running command, retrying");
+                self.exec(&cmd).await?.ok_or(Error::ConnectionClosed)?
+            }
+        };
+        self.warn_if_slow(&cmd, start);
+        Ok((p.id, self.response_body(p.body)?))
+    }
+
+    /// Like [`cmd`](Self::cmd), but checks up front that the command fits in one packet. Responses longer than a
+    /// packet are split by the server and read back as one, but there's no way to split a command, so one
+    /// longer than [`max_packet_length`](Self::max_packet_length) fails with [`Error::CommandTooLong`]. Commands with
+    /// a null byte fail with [`CommandError::NulByte`], as they do for [`run_command`](Self::run_command).
+    pub async fn cmd_chunked<C: ToString>(&mut self, cmd: C) -> Result<String> {
+        let cmd = cmd.to_string();
+        match command::check(&cmd, self.max_packet_length) {
+            Err(CommandError::TooLong(len)) => Err(Error::CommandTooLong {
+                len,
+                max: self.max_packet_length,
+            }),
+            Err(e) => Err(Error::Command(e)),
+            Ok(()) => self.cmd(cmd).await,
+        }
+    }
+
+    /// Like [`cmd`](Self::cmd), but wraps the body in a [`Response`] which checks it for the
+    /// [`error_hints`](Builder::error_hints) of servers which report failures as plain text.
+    pub async fn cmd_response<C: ToString>(&mut self, cmd: C) -> Result<Response> {
+        let body = self.cmd(cmd).await?;
+        Ok(Response::new(body, &self.error_hints))
+    }
+
+    /// Runs a command whose body isn't valid UTF-8, returning the raw response body.
+    /// Use [`cmd`](Self::cmd) for text, this isn't retried if the connection drops and skips the
+    /// server profile. Not supported by BattlEye connections.
+    pub async fn cmd_bytes(&mut self, body: Vec<u8>) -> Result<Vec<u8>> {
+        if self.protocol == Protocol::BattlEye {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::Unsupported,
+                "BattlEye commands must be text",
+            )));
+        }
+        if self.stream.is_none() {
+            self.connect().await?;
+        }
+        if !self.authenticated {
+            self.login().await?;
+        }
+        let id = self.next_id();
+        // the codec only handles text bodies, so its framer is driven directly around it
+        let mut parts = self.stream.take().unwrap().into_parts();
+        let res = self.exchange_raw(&mut parts, id, &body).await;
+        match res {
+            Ok(body) => {
+                self.stream = Some(Framed::from_parts(parts));
+                Ok(body)
+            }
+            Err(e) => {
+                self.drop_stream();
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes an `ExecCommand` packet with a raw `body` and reads until its response, skipping other packets.
+    /// Packets are framed by the stream's codec, and shown to the packet observer with their bodies as lossy text.
+    async fn exchange_raw(
+        &mut self,
+        parts: &mut FramedParts<Box<dyn Transport>, PacketCodec>,
+        id: i32,
+        body: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut out = BytesMut::new();
+        parts
+            .codec
+            .framer_mut()
+            .encode_bytes(PacketType::ExecCommand, id, body, &mut out)?;
+        let sent = Packet {
+            ptype: PacketType::ExecCommand,
+            id,
+            body: String::from_utf8_lossy(body).into_owned(),
+        };
+        self.id_trace.sent(&sent);
+        observe(&self.packet_observer, Direction::Sent, &sent);
+        parts.io.write_all(&out).await?;
+        parts.io.flush().await?;
+
+        loop {
+            while let Some((pid, ptype, body)) =
+                parts.codec.framer_mut().decode_bytes(&mut parts.read_buf)?
+            {
+                let p = Packet {
+                    ptype,
+                    id: pid,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                };
+                self.id_trace.received(&p, id);
+                observe(&self.packet_observer, Direction::Received, &p);
+                match pid {
+                    -1 => return Err(Error::Unauthenticated),
+                    pid if pid == id => return Ok(body.to_vec()),
+                    pid => trace!(
+                        "skipping packet {} which doesn't answer command {}",
+                        pid,
+                        id
+                    ),
+                }
+            }
+            if parts.io.read_buf(&mut parts.read_buf).await? == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+        }
+    }
+
+    /// Sends an empty command and returns how long the response took to arrive.
+    /// If the connection was dropped it is re-established once before giving up.
+    /// Over BattlEye the empty command is the protocol's keepalive, sent on the existing session.
+    pub async fn ping(&mut self) -> Result<Duration> {
+        for _ in 0..2 {
+            let open = match self.protocol {
+                Protocol::Source => self.stream.is_some(),
+                Protocol::BattlEye => self.battleye.is_some(),
+            };
+            if !open {
+                self.reconnect().await?;
+            }
+            let start = Instant::now();
+            if self.exec("").await?.is_some() {
+                return Ok(start.elapsed());
+            }
+            trace!("connection dropped during ping");
+        }
+        Err(Error::ConnectionClosed)
+    }
+
+    /// Creates a handle for running commands concurrently.
+    /// The handle opens its own connection with the same settings the first time it is used,
+    /// which is shared by all of its clones with responses routed back to callers by packet id.
+    pub fn handle(&self) -> ConnectionHandle {
+        ConnectionHandle::new(self.clone_config())
+    }
+
+    /// Hands the connection to a background task which keeps it logged in, reconnecting whenever
+    /// it's lost, and runs commands queued from any clone of the returned [`PersistentConnection`].
+    pub fn persistent(self) -> PersistentConnection {
+        PersistentConnection::new(self)
+    }
+
+    /// Renders `cmd` with the profile's escaping and runs it. Commands which can't be sent, see
+    /// [`Command::validate`], fail with [`Error::Command`] before anything is sent.
+    pub async fn run_command(&mut self, cmd: Command) -> Result<String> {
+        let cmd = self.profile.render(&cmd);
+        command::check(&cmd, self.max_packet_length)?;
+        self.cmd(cmd).await
+    }
+
+    /// Sends every command before reading any response, so a batch takes about one round trip, and returns
+    /// the responses in the order of `cmds`. Each command is followed by an empty command, whose response
+    /// marks the end of a response split across several packets. Unlike `cmd` a batch isn't retried if the
+    /// connection drops, since some commands may already have run. The id generator has to give unique ids.
+    pub async fn cmd_batch(&mut self, cmds: Vec<String>) -> Result<Vec<String>> {
+        if self.protocol == Protocol::BattlEye {
+            let mut responses = Vec::with_capacity(cmds.len());
+            for cmd in cmds {
+                responses.push(self.cmd(cmd).await?);
+            }
+            return Ok(responses);
+        }
+        if self.stream.is_none() {
+            self.connect().await?;
+        }
+        if !self.authenticated {
+            self.login().await?;
+        }
+        debug!("running a batch of {} commands", cmds.len());
+        // the command and terminator ids for each command
+        let mut ids = Vec::with_capacity(cmds.len());
+        let mut packets = Vec::with_capacity(cmds.len() * 2);
+        for cmd in &cmds {
+            let pk = Packet {
+                ptype: PacketType::ExecCommand,
+                id: self.next_id(),
+                body: self.profile.normalize(cmd).into_owned(),
+            };
+            let end = Packet {
+                ptype: PacketType::ExecCommand,
+                id: self.next_id(),
+                body: String::new(),
+            };
+            ids.push((pk.id, end.id));
+            packets.extend([pk, end]);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        let mut sent = Ok(());
+        for p in packets {
+            self.id_trace.sent(&p);
+            observe(&self.packet_observer, Direction::Sent, &p);
+            sent = stream.feed(p).await;
+            if sent.is_err() {
+                break;
+            }
+        }
+        if sent.is_ok() {
+            sent = stream.flush().await;
+        }
+        if let Err(e) = sent {
+            self.drop_stream();
+            return Err(Error::Io(e));
+        }
+
+        let mut bodies = vec![String::new(); cmds.len()];
+        let mut remaining = cmds.len();
+        while remaining > 0 {
+            let p = match self.stream.as_mut().unwrap().next().await {
+                Some(Ok(p)) => p,
+                Some(Err(e)) => {
+                    self.drop_stream();
+                    return Err(Error::from(e));
+                }
+                None => {
+                    self.drop_stream();
+                    return Err(Error::ConnectionClosed);
+                }
+            };
+            observe(&self.packet_observer, Direction::Received, &p);
+            if p.id == -1 {
+                debug!("server says the connection isn't authenticated");
+                self.authenticated = false;
+                self.needs_reauth = true;
+                return Err(Error::Unauthenticated);
+            }
+            match ids.iter().position(|&(id, end)| p.id == id || p.id == end) {
+                Some(i) if p.id == ids[i].0 => {
+                    self.id_trace.received(&p, p.id);
+                    bodies[i].push_str(&p.body);
+                }
+                Some(i) => {
+                    self.id_trace.received(&p, p.id);
+                    remaining -= 1;
+                    // a repeated terminator can't end the batch early
+                    ids[i].1 = -1;
+                }
+                None => trace!("ignoring packet {} which isn't part of the batch", p.id),
+            }
+        }
+        bodies.into_iter().map(|b| self.response_body(b)).collect()
+    }
+
+    /// Takes a packet the server sent unprompted, like pushed log output, if one has already arrived, returning
+    /// `Ok(None)` straight away otherwise and when not connected. Fails with `Error::ConnectionClosed` if the
+    /// server closed the connection.
+    pub fn try_recv(&mut self) -> Result<Option<String>> {
+        let res = match self.stream.as_mut().map(|s| s.next().now_or_never()) {
+            Some(Some(res)) => res,
+            _ => return Ok(None),
+        };
+        match res {
+            Some(Ok(p)) => {
+                observe(&self.packet_observer, Direction::Received, &p);
+                Ok(Some(p.body))
+            }
+            Some(Err(PacketError::Io(e))) if is_disconnect(&e) => {
+                trace!("connection lost: {}", e);
+                self.drop_stream();
+                Err(Error::ConnectionClosed)
+            }
+            Some(Err(e)) => {
+                self.drop_stream();
+                Err(Error::from(e))
+            }
+            None => {
+                self.drop_stream();
+                Err(Error::ConnectionClosed)
+            }
+        }
+    }
+
+    /// Flushes anything written to the connection but not yet sent. Commands flush once they are all written,
+    /// so this is only needed after writing to the stream some other way. Does nothing when not connected.
+    pub async fn flush(&mut self) -> Result<()> {
+        match self.stream.as_mut() {
+            Some(s) => Ok(s.flush().await?),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a command and parses the trimmed response, returning `Error::Parse` if it doesn't parse as `T`.
+    pub async fn cmd_parse<T: FromStr, C: ToString>(&mut self, cmd: C) -> Result<T> {
+        let body = self.cmd(cmd).await?;
+        body.trim().parse().map_err(|_| Error::Parse(body))
+    }
+
+    /// Runs a command and parses the first number in the response,
+    /// for responses with surrounding text like `There are 12 entities`.
+    pub async fn cmd_parse_first<T: FromStr, C: ToString>(&mut self, cmd: C) -> Result<T> {
+        let body = self.cmd(cmd).await?;
+        let parsed = body
+            .split(|c: char| !(c.is_ascii_digit() || c == '-' || c == '.'))
+            .filter(|s| s.chars().any(|c| c.is_ascii_digit()))
+            .find_map(|s| s.trim_end_matches('.').parse().ok());
+        parsed.ok_or(Error::Parse(body))
+    }
+
+    /// Shuts down the connection.
+    pub async fn close(mut self) -> Result<()> {
+        if let Some(s) = self.stream.take() {
+            trace!("closing connection to {}", self.host);
+            s.into_inner().shutdown().await?;
+        }
+        // BattlEye has no way to log out, the server forgets the session once it stops hearing from it
+        self.battleye = None;
+        Ok(())
+    }
+
+    /// Whether the server stopped accepting this connection's login, for example after its password was rotated
+    /// or its session expired. Set when a command is answered with the `-1` id servers use for unauthenticated
+    /// requests, which fails the command with `Error::Unauthenticated`, or when logging in again fails.
+    /// The next command logs in again before it is sent, or it can be done up front with [`reauthenticate`](Self::reauthenticate).
+    pub fn needs_reauth(&self) -> bool {
+        self.needs_reauth
+    }
+
+    /// Logs in again over the current connection, reconnecting first if there isn't one.
+    pub async fn reauthenticate(&mut self) -> Result<()> {
+        if self.stream.is_none() && self.battleye.is_none() {
+            self.connect().await?;
+        }
+        self.login().await
+    }
+
+    /// Like [`reauthenticate`](Self::reauthenticate), but a rejected password is `Ok(false)` rather than
+    /// `Error::AuthFailed`, so only io and protocol failures are errors.
+    pub async fn try_login(&mut self) -> Result<bool> {
+        match self.reauthenticate().await {
+            Ok(()) => Ok(true),
+            Err(Error::AuthFailed) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Turns the connection into a stream of the `ResponseValue` bodies the server sends, for tailing servers which
+    /// push log output without being asked. This consumes the connection, so no more commands can be sent, and
+    /// the stream ends when the server closes the connection. Only supported for the Source protocol.
+    pub fn into_response_stream(mut self) -> impl futures::Stream<Item = Result<String>> {
+        let observer = self.packet_observer.clone();
+        match self.stream.take() {
+            Some(s) => Either::Left(s.filter_map(move |res| {
+                if let Ok(p) = &res {
+                    observe(&observer, Direction::Received, p);
+                }
+                async move {
+                    match res {
+                        Ok(p) if p.ptype == PacketType::ResponseValue => Some(Ok(p.body)),
+                        Ok(p) => {
+                            trace!("skipping {:?} packet in response stream", p.ptype);
+                            None
+                        }
+                        Err(e) => Some(Err(Error::from(e))),
+                    }
+                }
+            })),
+            None => Either::Right(stream::once(async {
+                Err(Error::Io(io::Error::new(
+                    ErrorKind::NotConnected,
+                    "Not connected",
+                )))
+            })),
+        }
+    }
+
+    /// The longest packet the connection sends or accepts, set by [`Builder::max_packet_length`].
+    pub fn max_packet_length(&self) -> usize {
+        self.max_packet_length
+    }
+
+    /// The server profile the connection was built with.
+    pub fn profile(&self) -> &ServerProfile {
+        &self.profile
+    }
+
+    /// Whether the connection is open and authenticated.
+    /// A dropped connection is only noticed on the next command, see [`ConnectionHandle::is_healthy`] for a live check.
+    pub fn is_healthy(&self) -> bool {
+        (self.stream.is_some() || self.battleye.is_some()) && self.authenticated
+    }
+
+    /// The most recent packet ids sent and received, oldest first, for tracking down id mismatches with a server.
+    /// Only the last 256 events are kept.
+    #[cfg(feature = "debug-ids")]
+    pub fn id_trace(&self) -> Vec<IdTraceEntry> {
+        self.id_trace.entries()
+    }
+
+    /// The number of inbound packets skipped on the current connection for exceeding the maximum packet length.
+    pub fn skipped_packets(&self) -> u64 {
+        self.stream
+            .as_ref()
+            .map_or(0, |s| s.codec().skipped_packets())
+    }
+}
+
+impl fmt::Debug for Connection {
+    /// Shows where the connection goes and how it retries, with the password redacted.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("host", &self.host)
+            .field("password", &"<redacted>")
+            .field("authenticated", &self.authenticated)
+            .field("max_retries", &self.max_retries)
+            .field("retry_delay", &self.retry_delay)
+            .field("exponential_backoff", &self.exponential_backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Connection {
+    /// Shuts the stream down on a spawned task if the connection wasn't closed, when dropped inside a runtime.
+    /// Outside of one the socket is just closed.
+    fn drop(&mut self) {
+        if let Some(s) = self.stream.take() {
+            trace!("dropping a connection to {} which wasn't closed", self.host);
+            if let Ok(rt) = tokio::runtime::Handle::try_current() {
+                rt.spawn(async move {
+                    if let Err(e) = s.into_inner().shutdown().await {
+                        trace!("failed to shut down dropped connection: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+// private methods
+impl Connection {
+    /// Sends a single command and reads its response, reconnecting first if there is no stream.
+    /// Returns `None` and clears the stream if the server dropped the connection.
+    async fn exec(&mut self, cmd: &str) -> Result<Option<Packet>> {
+        if self.protocol == Protocol::BattlEye {
+            return self.exec_battleye(cmd).await;
+        }
+        let pk = Packet {
+            ptype: PacketType::ExecCommand,
+            id: self.next_id(),
+            body: cmd.to_owned(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("id", pk.id);
+        if self.stream.is_none() {
+            self.open().await?;
+        }
+        // only unauthenticated here when the login is pipelined with this command
+        let auth = if self.authenticated {
+            None
+        } else {
+            Some(self.auth_packet())
+        };
+        let aid = auth.as_ref().map(|a| a.id);
+        let id = pk.id;
+        let stream = self.stream.as_mut().unwrap();
+
+        let mut sent = Ok(());
+        if let Some(auth) = auth {
+            self.id_trace.sent(&auth);
+            observe(&self.packet_observer, Direction::Sent, &auth);
+            sent = stream.feed(auth).await;
+        }
+        if sent.is_ok() {
+            self.id_trace.sent(&pk);
+            observe(&self.packet_observer, Direction::Sent, &pk);
+            sent = stream.send(pk).await;
+        }
+        if let (Some(aid), Ok(())) = (aid, &sent) {
+            if let Err(e) = self.read_auth(aid).await {
+                self.drop_stream();
+                return Err(e);
+            }
+        }
+        let stream = self.stream.as_mut().unwrap();
+        let res = match sent {
+            Ok(()) => loop {
+                let res = stream.next().await;
+                match &res {
+                    Some(Ok(p))
+                        if self.server_kind == ServerKind::Minecraft
+                            && p.id != id
+                            && p.id != -1 =>
+                    {
+                        trace!(
+                            "skipping packet {} which doesn't answer command {}",
+                            p.id,
+                            id
+                        );
+                        observe(&self.packet_observer, Direction::Received, p);
+                    }
+                    _ => break res,
+                }
+            },
+            Err(e) => Some(Err(PacketError::Io(e))),
+        };
+
+        match res {
+            Some(Ok(p)) => {
+                self.id_trace.received(&p, id);
+                observe(&self.packet_observer, Direction::Received, &p);
+                if p.id == -1 {
+                    debug!("server says the connection isn't authenticated");
+                    self.authenticated = false;
+                    self.needs_reauth = true;
+                    return Err(Error::Unauthenticated);
+                }
+                Ok(Some(p))
+            }
+            Some(Err(PacketError::Io(e))) if is_disconnect(&e) => {
+                trace!("connection lost: {}", e);
+                self.drop_stream();
+                Ok(None)
+            }
+            Some(Err(e)) => {
+                // the framing can't be trusted after a decode error
+                trace!("failed to decode response: {}", e);
+                self.drop_stream();
+                Err(Error::from(e))
+            }
+            None => {
+                trace!("server ended the connection");
+                self.drop_stream();
+                Ok(None)
+            }
+        }
+    }
+
+    async fn exec_battleye(&mut self, cmd: &str) -> Result<Option<Packet>> {
+        if !self.authenticated {
+            if self.battleye.is_none() {
+                self.connect().await?;
+            }
+            self.login().await?;
+        }
+        let session = self.battleye.as_mut().expect("connected after login");
+        match session.cmd(cmd).await {
+            Ok((seq, body)) => Ok(Some(Packet {
+                ptype: PacketType::ResponseValue,
+                id: seq as i32,
+                body,
+            })),
+            Err(Error::Io(e)) if e.kind() == ErrorKind::TimedOut => {
+                trace!("BattlEye server stopped responding: {}", e);
+                self.drop_stream();
+                Ok(None)
+            }
+            Err(e) => {
+                self.drop_stream();
+                Err(e)
+            }
+        }
+    }
+
+    /// Copies the settings into a new, unconnected `Connection`.
+    fn clone_config(&self) -> Connection {
+        Connection {
+            stream: None,
+            battleye: None,
+            id_trace: IdTrace::default(),
+            host: self.host.clone(),
+            password: self.password.clone(),
+            authenticated: false,
+            needs_reauth: false,
+            fallbacks: self.fallbacks.clone(),
+            max_retries: self.max_retries,
+            retry_delay: self.retry_delay,
+            exponential_backoff: self.exponential_backoff,
+            structured_errors: self.structured_errors,
+            slow_command_threshold: self.slow_command_threshold,
+            max_packet_length: self.max_packet_length,
+            bind_address: self.bind_address,
+            tcp_nodelay: self.tcp_nodelay,
+            keepalive_interval: self.keepalive_interval,
+            auto_reconnect: self.auto_reconnect,
+            command_timeout: self.command_timeout,
+            max_pending_requests: self.max_pending_requests,
+            backpressure: self.backpressure,
+            pipeline_auth: self.pipeline_auth,
+            protocol: self.protocol,
+            jitter: self.jitter,
+            auth_skip_packets: self.auth_skip_packets,
+            packet_observer: self.packet_observer.clone(),
+            resolver: Arc::clone(&self.resolver),
+            max_retry_delay: self.max_retry_delay,
+            round_robin_dns: self.round_robin_dns,
+            auth_max_retries: self.auth_max_retries,
+            auth_id: self.auth_id,
+            server_kind: self.server_kind,
+            error_hints: self.error_hints.clone(),
+            dns_ttl: self.dns_ttl,
+            resolved_at: self.resolved_at,
+            tcp_keepalive: self.tcp_keepalive,
+            id_generator: Arc::clone(&self.id_generator),
+            profile: self.profile.clone(),
+            #[cfg(feature = "proxy")]
+            proxy: self.proxy.clone(),
+            #[cfg(test)]
+            opened: Default::default(),
+        }
+    }
+
+    fn warn_if_slow(&self, cmd: &str, start: Instant) {
+        let elapsed = start.elapsed();
+        if self.slow_command_threshold.is_some_and(|t| elapsed > t) {
+            warn!(
+                "slow command \"{}\" took {:?}",
+                command_prefix(cmd),
+                elapsed
+            );
+        }
+    }
+
+    /// Decodes structured errors from compatible servers if they are enabled.
+    fn response_body(&self, body: String) -> Result<String> {
+        match body.strip_prefix(ERROR_PREFIX) {
+            Some(msg) if self.structured_errors => Err(Error::CommandRejected(msg.to_owned())),
+            _ => Ok(body),
+        }
+    }
+
+    fn next_id(&self) -> i32 {
+        self.id_generator.next_id() & i32::MAX
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        trace!("reconnecting");
+        self.connect().await?;
+        self.login_retrying().await
+    }
+
+    /// Connects, logging in unless the login is pipelined with the next command.
+    async fn open(&mut self) -> Result<()> {
+        self.connect().await?;
+        if !self.pipeline_auth {
+            self.login_retrying().await?;
+        }
+        Ok(())
+    }
+
+    /// Logs in over the new connection, reconnecting and trying again up to `auth_max_retries` times
+    /// if it fails for any reason but a rejected password.
+    async fn login_retrying(&mut self) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.login().await {
+                Err(e) if attempt < self.auth_max_retries && !matches!(e, Error::AuthFailed) => {
+                    attempt += 1;
+                    debug!("failed to log in, retrying #{}: {}", attempt, e);
+                    self.drop_stream();
+                    sleep(self.backoff_delay(attempt)).await;
+                    self.connect().await?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn drop_stream(&mut self) {
+        self.stream = None;
+        self.battleye = None;
+        self.authenticated = false;
+    }
+
+    /// Connects to the current endpoint, failing over to each fallback in turn if it can't be reached.
+    async fn connect(&mut self) -> io::Result<()> {
+        let mut res = self.connect_endpoint().await;
+        for _ in 0..self.fallbacks.len() {
+            let e = match res {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            let (host, password) = self.fallbacks.pop_front().unwrap();
+            warn!("{}, failing over to {}", e, host);
+            let old_host = std::mem::replace(&mut self.host, host);
+            let old_password = std::mem::replace(&mut self.password, password);
+            self.fallbacks.push_back((old_host, old_password));
+            res = self.connect_endpoint().await;
+        }
+        res
+    }
+
+    async fn connect_endpoint(&mut self) -> io::Result<()> {
+        if self.protocol == Protocol::BattlEye {
+            let addr = match self.host {
+                Endpoint::Host { addr, .. } => addr,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "BattlEye connections must be made to a socket address",
+                    ))
+                }
+            };
+            trace!("opening BattlEye session with {}", addr);
+            self.battleye =
+                Some(battleye::Session::connect(addr, self.retry_delay, self.max_retries).await?);
+            return Ok(());
+        }
+        if let Endpoint::Io = self.host {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "a custom transport can't be reopened",
+            ));
+        }
+        let mut last_err = None;
+        for retries in 1..self.max_retries + 1 {
+            // the first attempt reuses the cached address and the host is only looked up again once
+            // that fails, retries after that stay on the cached addresses
+            if retries == 2 {
+                self.re_resolve().await;
+            }
+            if retries > 1 {
+                self.next_address();
+            }
+            trace!("Attempting to connect to {} #{}", &self.host, retries);
+            let s = match self.open_transport(&self.host).await {
+                Ok(s) => s,
+                Err(e) => {
+                    trace!("failed to connect to server: {}", e);
+                    last_err = Some(e);
+                    sleep(self.backoff_delay(retries)).await;
+                    continue;
+                }
+            };
+
+            self.stream = Some(Framed::new(
+                s,
+                PacketCodec::new(CodecType::Client, self.max_packet_length),
+            ));
+
+            return Ok(());
+        }
+        Err(match last_err {
+            Some(e) => io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to connect to {} after {} attempts: {}",
+                    self.host, self.max_retries, e
+                ),
+            ),
+            None => io::Error::new(
+                io::ErrorKind::NotConnected,
+                "no connection attempts were made",
+            ),
+        })
+    }
+
+    /// Looks the host name up again, switching to its first new address if it moved.
+    async fn re_resolve(&mut self) {
+        let (host, current) = match &self.host {
+            Endpoint::Host {
+                host: Some(host),
+                addr,
+                ..
+            } => (host.clone(), *addr),
+            _ => return,
+        };
+        if self.resolved_at.elapsed() < self.dns_ttl {
+            trace!("reusing {} for {} until the dns ttl expires", current, host);
+            return;
+        }
+        let addrs = match (self.resolver)(host.clone()).await {
+            Ok(addrs) => {
+                self.resolved_at = Instant::now();
+                addrs
+            }
+            Err(e) => {
+                warn!(
+                    "failed to resolve {} again, keeping {}: {}",
+                    host, current, e
+                );
+                return;
+            }
+        };
+        let addr = match addrs.first() {
+            Some(_) if addrs.contains(&current) => current,
+            Some(&addr) => {
+                debug!("{} now resolves to {}", host, addr);
+                addr
+            }
+            None => return,
+        };
+        self.host = Endpoint::Host {
+            host: Some(host),
+            addr,
+            addrs,
+        };
+    }
+
+    /// With `round_robin_dns`, moves on to the next of the cached addresses.
+    fn next_address(&mut self) {
+        if !self.round_robin_dns {
+            return;
+        }
+        if let Endpoint::Host { addr, addrs, .. } = &mut self.host {
+            if let Some(i) = addrs.iter().position(|a| a == addr) {
+                *addr = addrs[(i + 1) % addrs.len()];
+            }
+        }
+    }
+
+    /// How long to wait after the `attempt`th failed connection attempt, counting from 1.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let delay = if self.exponential_backoff {
+            self.retry_delay
+                .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        } else {
+            self.retry_delay
+        };
+        // capped first as well, so jitter can't overflow a saturated delay
+        let delay = delay.min(self.max_retry_delay);
+        let delay = if self.jitter > 0.0 {
+            let factor = rand::thread_rng().gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+            delay.mul_f64(factor)
+        } else {
+            delay
+        };
+        delay.min(self.max_retry_delay)
+    }
+
+    async fn open_transport(&self, host: &Endpoint) -> io::Result<Box<dyn Transport>> {
+        match host {
+            Endpoint::Host { addr, .. } => Ok(Box::new(self.open_stream(*addr).await?)),
+            #[cfg(all(unix, feature = "unix"))]
+            Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+            Endpoint::Io => unreachable!("custom transports are never reopened"),
+        }
+    }
+
+    /// Opens a tcp stream to `addr`, through the proxy if one is configured.
+    async fn open_stream(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let s = self.open_tcp(addr).await?;
+        if self.tcp_nodelay {
+            if let Err(e) = s.set_nodelay(true) {
+                warn!("failed to set TCP_NODELAY: {}", e);
+            }
+        }
+        if let Some(time) = self.tcp_keepalive {
+            if let Err(e) = set_tcp_keepalive(&s, time) {
+                warn!("failed to set SO_KEEPALIVE: {}", e);
+            }
+        }
+        #[cfg(test)]
+        {
+            *self.opened.lock().unwrap() = Some(OpenedSocket {
+                local_addr: s.local_addr()?,
+                nodelay: s.nodelay()?,
+            });
+        }
+        Ok(s)
+    }
+
+    async fn open_tcp(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        #[cfg(feature = "proxy")]
+        if let Some(proxy) = &self.proxy {
+            use tokio_socks::tcp::Socks5Stream;
+            let socket = self.tcp_connect(proxy.addr).await?;
+            let s = match &proxy.credentials {
+                Some((user, pass)) => {
+                    Socks5Stream::connect_with_password_and_socket(socket, addr, user, pass).await
+                }
+                None => Socks5Stream::connect_with_socket(socket, addr).await,
+            };
+            return match s {
+                Ok(s) => Ok(s.into_inner()),
+                Err(tokio_socks::Error::Io(e)) => Err(e),
+                Err(e) => Err(IoError::other(e)),
+            };
+        }
+        self.tcp_connect(addr).await
+    }
+
+    /// Connects to `addr`, from the bind address if one is configured.
+    async fn tcp_connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        match self.bind_address {
+            Some(local) => {
+                let socket = if local.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                socket.bind(local)?;
+                socket.connect(addr).await
+            }
+            None => TcpStream::connect(addr).await,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(host = %self.host, id = tracing::field::Empty))
+    )]
+    async fn login(&mut self) -> Result<()> {
+        if let Some(session) = self.battleye.as_mut() {
+            self.authenticated = false;
+            if !session.login(&self.password).await? {
+                trace!("authentication failed");
+                self.needs_reauth = true;
+                return Err(Error::AuthFailed);
+            }
+            self.authenticated = true;
+            self.needs_reauth = false;
+            return Ok(());
+        }
+        let pk = self.auth_packet();
+        let aid = pk.id;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("id", aid);
+
+        self.id_trace.sent(&pk);
+        observe(&self.packet_observer, Direction::Sent, &pk);
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not connected"))?
+            .send(pk)
+            .await?;
+        self.read_auth(aid).await
+    }
+
+    fn auth_packet(&mut self) -> Packet {
+        self.authenticated = false;
+        Packet {
+            ptype: PacketType::Auth,
+            id: self.auth_id.unwrap_or_else(|| self.next_id()),
+            body: self.password.clone(),
+        }
+    }
+
+    /// Reads the response to the auth packet with id `aid`.
+    async fn read_auth(&mut self, aid: i32) -> Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not connected"))?;
+
+        // the auth response itself plus the packets allowed before it
+        for _ in 0..=self.server_kind.auth_skip_packets(self.auth_skip_packets) {
+            let res = stream.next().await;
+            if let Some(Ok(p)) = &res {
+                self.id_trace.received(p, aid);
+                observe(&self.packet_observer, Direction::Received, p);
+            }
+            match res {
+                Some(Ok(p)) if p.ptype == PacketType::AuthResponse => {
+                    // the spec signals a failed login with an id of -1, whatever the request id was
+                    return if p.id == -1 {
+                        trace!("authentication failed");
+                        self.needs_reauth = true;
+                        Err(Error::AuthFailed)
+                    } else if p.id == aid {
+                        trace!("client successfully logged in");
+                        self.authenticated = true;
+                        self.needs_reauth = false;
+                        Ok(())
+                    } else {
+                        trace!("auth response id {} doesn't match request id {}", p.id, aid);
+                        Err(Error::InvalidResponse)
+                    };
+                }
+                Some(Ok(_)) => {
+                    trace!("client recieved non auth response when reading for auth response");
+                    continue;
+                }
+                Some(Err(e)) => {
+                    trace!("read error from buffer: {:?}", e);
+                    continue;
+                } // fix this
+                None => {
+                    trace!("stream ended while waiting for auth response");
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "the server closed the connection before answering the login",
+                    )));
+                }
+            }
+        }
+        Err(Error::InvalidResponse)
+    }
+}
+
+/// The start of a command, short enough for log messages.
+fn command_prefix(cmd: &str) -> &str {
+    match cmd.char_indices().nth(32) {
+        Some((i, _)) => &cmd[..i],
+        None => cmd,
+    }
+}
+
+/// Whether an io error means the peer has gone away.
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+    )
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Error type
+#[derive(Debug)]
+pub enum Error {
+    /// For io Errors
+    Io(io::Error),
+    /// A packet couldn't be decoded, io errors are returned as `Error::Io` instead
+    Packet(PacketError),
+    /// Invalide Response
+    InvalidResponse,
+    /// The password was empty or only whitespace and password validation was enabled
+    BlankPassword,
+    /// The password was empty and empty passwords weren't allowed
+    EmptyPassword,
+    /// The [`Builder::auth_id`] was `-1`, which can't be told apart from a rejected password
+    InvalidAuthId,
+    /// The environment variable holding the password wasn't set, contains its name
+    MissingEnv(String),
+    /// A compatible server reported that the command failed
+    CommandRejected(String),
+    /// The response couldn't be parsed, contains the response body
+    Parse(String),
+    /// The server rejected the password
+    AuthFailed,
+    /// The server answered a command as if the connection had never logged in, see [`Connection::needs_reauth`]
+    Unauthenticated,
+    /// The connection was lost while the request was waiting for its response
+    Disconnected,
+    /// The server closed the connection before responding, again after reconnecting to retry the command
+    /// unless the connection can't be reopened. The next command reconnects. An empty response is `Ok("")`
+    ConnectionClosed,
+    /// Too many requests were already waiting on responses
+    Busy,
+    /// A [`Command`] couldn't be sent as it is
+    Command(CommandError),
+    /// The command's packet would be `len` bytes, over the `max` a single packet can carry
+    CommandTooLong {
+        /// The length of the packet the command needs
+        len: usize,
+        /// The connection's [`max_packet_length`](Connection::max_packet_length)
+        max: usize,
+    },
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<CommandError> for Error {
+    fn from(err: CommandError) -> Self {
+        Self::Command(err)
+    }
+}
+
+impl From<PacketError> for Error {
+    fn from(err: PacketError) -> Self {
+        match err {
+            PacketError::Io(e) => Error::Io(e),
+            e => Error::Packet(e),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> result::Result<(), fmt::Error> {
+        match self {
+            Error::Io(e) => {
+                write!(f, "Io Error: {}", e)
+            }
+            Error::Packet(e) => {
+                write!(f, "Packet Error: {}", e)
+            }
+            Error::InvalidResponse => {
+                write!(f, "Invalid Response")
+            }
+            Error::BlankPassword => {
+                write!(f, "Password is empty or only whitespace")
+            }
+            Error::InvalidAuthId => {
+                write!(f, "The auth id can't be -1, the id of a rejected login")
+            }
+            Error::MissingEnv(var) => {
+                write!(f, "The password environment variable {} is not set", var)
+            }
+            Error::EmptyPassword => {
+                write!(
+                    f,
+                    "Password is empty, use allow_empty_password for servers without one"
+                )
+            }
+            Error::CommandRejected(msg) => {
+                write!(f, "Command Rejected: {}", msg)
+            }
+            Error::Parse(body) => {
+                write!(f, "Failed to parse response: {:?}", body)
+            }
+            Error::AuthFailed => {
+                write!(f, "authentication failed: incorrect password")
+            }
+            Error::Unauthenticated => {
+                write!(f, "the server no longer accepts this connection's login")
+            }
+            Error::Disconnected => {
+                write!(f, "Disconnected while waiting for a response")
+            }
+            Error::ConnectionClosed => {
+                write!(f, "The server closed the connection")
+            }
+            Error::Busy => {
+                write!(f, "Too many pending requests")
+            }
+            Error::Command(e) => {
+                write!(f, "Invalid Command: {}", e)
+            }
+            Error::CommandTooLong { len, max } => {
+                write!(
+                    f,
+                    "The command's packet is {} bytes, over the {} byte limit, commands can't be split across packets",
+                    len, max
+                )
+            }
+        }
+    }
+}
+
+impl ErrorTrait for Error {
+    fn source(&self) -> Option<&(dyn ErrorTrait + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Packet(e) => Some(e),
+            Error::Command(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        pin::Pin,
+        sync::atomic::{AtomicBool, AtomicI32, Ordering},
+        task::{Context, Poll},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::net::TcpListener;
+
+    pub(super) type ServerStream = Stream;
+
+    /// A logger that keeps every message so tests can assert on them.
+    struct CaptureLogger;
+
+    static LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            LOGS.lock()
+                .unwrap()
+                .push(format!("{} {}", record.level(), record.args()));
+        }
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    fn logged(needle: &str) -> bool {
+        LOGS.lock().unwrap().iter().any(|l| l.contains(needle))
+    }
+
+    pub(super) async fn accept(listener: &TcpListener) -> ServerStream {
+        let (socket, _) = listener.accept().await.unwrap();
+        server_stream(socket)
+    }
+
+    pub(super) fn server_stream<T: Transport + 'static>(io: T) -> ServerStream {
+        Framed::new(Box::new(io), PacketCodec::new(CodecType::Server, 4096))
+    }
+
+    /// Reads an auth packet and accepts it, whatever the password.
+    pub(super) async fn accept_auth(stream: &mut ServerStream) {
+        let p = stream.next().await.unwrap().unwrap();
+        assert_eq!(p.ptype, PacketType::Auth);
+        stream
+            .send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Reads a command and responds with `echo: <command>`.
+    pub(super) async fn echo(stream: &mut ServerStream) {
+        let p = stream.next().await.unwrap().unwrap();
+        assert_eq!(p.ptype, PacketType::ExecCommand);
+        stream
+            .send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: format!("echo: {}", p.body),
+            })
+            .await
+            .unwrap();
+    }
+
+    impl Connection {
+        /// The last tcp stream the connection opened.
+        fn opened_socket(&self) -> OpenedSocket {
+            self.opened
+                .lock()
+                .unwrap()
+                .expect("a tcp stream was opened")
+        }
+    }
+
+    /// Returns an address on localhost that nothing is listening on.
+    async fn closed_port() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn errors_chain_their_source() {
+        let err = Error::from(IoError::new(ErrorKind::ConnectionReset, "reset"));
+        let source = err.source().expect("io errors have a source");
+        let io = source.downcast_ref::<IoError>().unwrap();
+        assert_eq!(io.kind(), ErrorKind::ConnectionReset);
+        let err = Error::from(PacketError::InvalidLength);
+        assert!(err.source().unwrap().is::<PacketError>());
+        assert!(Error::AuthFailed.source().is_none());
+    }
+
+    #[tokio::test]
+    async fn lazy_connections_connect_on_the_first_command() {
+        let addr = closed_port().await;
+        let mut c = Connection::builder().lazy(addr, "password").await.unwrap();
+        assert!(!c.is_healthy());
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+        assert!(c.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn connect_refused_reports_underlying_error() {
+        let addr = closed_port().await;
+        let err = Connection::builder()
+            .max_retries(2)
+            .retry_delay(Duration::from_millis(1))
+            .connect(addr, "password")
+            .await
+            .expect_err("connecting to a closed port should fail");
+        match err {
+            Error::Io(e) => assert_eq!(e.kind(), ErrorKind::ConnectionRefused),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn blank_password_rejected_when_validating() {
+        let addr = closed_port().await;
+        let err = Connection::builder()
+            .validate_password(true)
+            .connect(addr, " \t ")
+            .await
+            .expect_err("a blank password should be rejected");
+        assert!(matches!(err, Error::BlankPassword));
+    }
+
+    #[tokio::test]
+    async fn blank_password_allowed_by_default() {
+        let addr = closed_port().await;
+        let err = Connection::builder()
+            .max_retries(1)
+            .retry_delay(Duration::from_millis(1))
+            .connect(addr, " ")
+            .await
+            .expect_err("connecting to a closed port should fail");
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[tokio::test]
+    async fn cmd_reconnects_after_server_drops_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut first = accept(&listener).await;
+            accept_auth(&mut first).await;
+            echo(&mut first).await;
+            drop(first);
+
+            let mut second = accept(&listener).await;
+            accept_auth(&mut second).await;
+            echo(&mut second).await;
+        });
+
+        let mut c = Connection::builder()
+            .retry_delay(Duration::from_millis(1))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("one").await.unwrap(), "echo: one");
+        assert_eq!(c.cmd("two").await.unwrap(), "echo: two");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn structured_errors_are_opt_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                accept_auth(&mut s).await;
+                let p = s.next().await.unwrap().unwrap();
+                s.send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: p.id,
+                    body: format!("{}no such command", ERROR_PREFIX),
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        let mut plain = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(
+            plain.cmd("foo").await.unwrap(),
+            format!("{}no such command", ERROR_PREFIX)
+        );
+
+        let mut structured = Connection::builder()
+            .structured_errors(true)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        match structured.cmd("foo").await {
+            Err(Error::CommandRejected(msg)) => assert_eq!(msg, "no such command"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        server.await.unwrap();
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn server_process_error_is_typed_client_error() {
+        use crate::server::{RconImpl, ServerSession, ShareMap};
+        use std::sync::Arc;
+        use tokio::sync::RwLock;
+
+        struct Failing;
+
+        #[async_trait::async_trait]
+        impl RconImpl for Failing {
+            fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+                Failing
+            }
+            async fn authenticate(&mut self, password: String, _: i32) -> bool {
+                password == "password"
+            }
+            async fn process(&mut self, cmd: String) -> anyhow::Result<String> {
+                Err(anyhow::anyhow!("unknown command {}", cmd))
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let state = Arc::new(RwLock::new(ShareMap::custom()));
+            ServerSession::from_tcp_stream(socket, Failing::new(state))
+                .start()
+                .await
+        });
+
+        let mut c = Connection::builder()
+            .structured_errors(true)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        match c.cmd("foo").await {
+            Err(Error::CommandRejected(msg)) => assert_eq!(msg, "unknown command foo"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_command_logs_warning() {
+        capture_logs();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            let p = s.next().await.unwrap().unwrap();
+            sleep(Duration::from_secs(5)).await;
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+        });
+
+        let mut c = Connection::builder()
+            .slow_command_threshold(Duration::from_secs(1))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.cmd("slow command check").await.unwrap();
+        assert!(logged("WARN slow command \"slow command check\""));
+    }
+
+    /// A minimal SOCKS5 proxy for a single connection, requiring `user`/`pass` if given.
+    #[cfg(feature = "proxy")]
+    async fn socks5_proxy(listener: TcpListener, credentials: Option<(&str, &str)>) {
+        use tokio::io::copy_bidirectional;
+
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut head = [0u8; 2];
+        client.read_exact(&mut head).await.unwrap();
+        let mut methods = vec![0u8; head[1] as usize];
+        client.read_exact(&mut methods).await.unwrap();
+        match credentials {
+            Some((user, pass)) => {
+                assert!(methods.contains(&0x02));
+                client.write_all(&[0x05, 0x02]).await.unwrap();
+                let mut ver_len = [0u8; 2];
+                client.read_exact(&mut ver_len).await.unwrap();
+                let mut u = vec![0u8; ver_len[1] as usize];
+                client.read_exact(&mut u).await.unwrap();
+                let mut plen = [0u8; 1];
+                client.read_exact(&mut plen).await.unwrap();
+                let mut p = vec![0u8; plen[0] as usize];
+                client.read_exact(&mut p).await.unwrap();
+                assert_eq!((&u[..], &p[..]), (user.as_bytes(), pass.as_bytes()));
+                client.write_all(&[0x01, 0x00]).await.unwrap();
+            }
+            None => client.write_all(&[0x05, 0x00]).await.unwrap(),
+        }
+
+        // only ipv4 CONNECT requests are needed by the tests
+        let mut req = [0u8; 10];
+        client.read_exact(&mut req).await.unwrap();
+        assert_eq!(&req[..4], &[0x05, 0x01, 0x00, 0x01]);
+        let ip = std::net::Ipv4Addr::new(req[4], req[5], req[6], req[7]);
+        let port = u16::from_be_bytes([req[8], req[9]]);
+        let mut upstream = TcpStream::connect((ip, port)).await.unwrap();
+        client
+            .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let _ = copy_bidirectional(&mut client, &mut upstream).await;
+    }
+
+    #[cfg(feature = "proxy")]
+    #[tokio::test]
+    async fn connects_through_socks5_proxy() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&server).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(socks5_proxy(proxy, Some(("user", "hunter2"))));
+
+        let mut c = Connection::builder()
+            .proxy(ProxyConfig::new(proxy_addr).credentials("user", "hunter2"))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn connects_from_bind_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let mut s = server_stream(socket);
+            accept_auth(&mut s).await;
+            peer
+        });
+
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let c = Connection::builder()
+            .bind_address(local)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let peer = server.await.unwrap();
+        let stream_addr = c.opened_socket().local_addr;
+        assert_eq!(peer, stream_addr);
+        assert_eq!(peer.ip(), local.ip());
+    }
+
+    #[tokio::test]
+    async fn sets_tcp_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                accept_auth(&mut s).await;
+            }
+        });
+
+        let c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert!(c.opened_socket().nodelay);
+
+        let c = Connection::builder()
+            .tcp_nodelay(false)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert!(!c.opened_socket().nodelay);
+    }
+
+    #[tokio::test]
+    async fn sets_tcp_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            loop {
+                let _ = listener.accept().await;
+            }
+        });
+
+        let mut c = Connection::builder()
+            .tcp_keepalive(Some(Duration::from_secs(30)))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let s = c.open_stream(addr).await.unwrap();
+        assert!(socket2::SockRef::from(&s).keepalive().unwrap());
+        c.tcp_keepalive = None;
+        let s = c.open_stream(addr).await.unwrap();
+        assert!(!socket2::SockRef::from(&s).keepalive().unwrap());
+    }
+
+    /// Accepts a login and answers every command with its packet id.
+    async fn id_echo_server(listener: TcpListener) -> Vec<i32> {
+        let mut s = accept(&listener).await;
+        let auth = s.next().await.unwrap().unwrap();
+        s.send(Packet {
+            ptype: PacketType::AuthResponse,
+            id: auth.id,
+            body: String::new(),
+        })
+        .await
+        .unwrap();
+        let mut ids = vec![auth.id];
+        while let Some(Ok(p)) = s.next().await {
+            ids.push(p.id);
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: p.id.to_string(),
+            })
+            .await
+            .unwrap();
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn ids_are_sequential_by_default() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(id_echo_server(listener));
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("a").await.unwrap(), "2");
+        assert_eq!(c.cmd("b").await.unwrap(), "3");
+        drop(c);
+        assert_eq!(server.await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn fixed_ids_put_exact_bytes_on_the_wire() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut auth = [0; 18];
+            server.read_exact(&mut auth).await.unwrap();
+            server
+                .write_all(&[10, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let mut cmd = [0; 18];
+            server.read_exact(&mut cmd).await.unwrap();
+            server
+                .write_all(&[12, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, b'o', b'k', 0, 0])
+                .await
+                .unwrap();
+            (auth, cmd)
+        });
+
+        let mut c = Connection::builder()
+            .id_source(FixedIdSource::new([7, 8]))
+            .connect_io(client, "pass")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "ok");
+        let (auth, cmd) = server.await.unwrap();
+        assert_eq!(
+            auth,
+            [14, 0, 0, 0, 7, 0, 0, 0, 3, 0, 0, 0, b'p', b'a', b's', b's', 0, 0]
+        );
+        assert_eq!(
+            cmd,
+            [14, 0, 0, 0, 8, 0, 0, 0, 2, 0, 0, 0, b'l', b'i', b's', b't', 0, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_id_generator_is_non_negative() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(id_echo_server(listener));
+
+        let mut c = Connection::builder()
+            .id_generator(|| -1)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.cmd("a").await.unwrap();
+        drop(c);
+        assert!(server.await.unwrap().iter().all(|id| *id >= 0));
+    }
+
+    /// Accepts a login and answers each command with the next of `responses`.
+    async fn scripted_server(responses: Vec<&'static str>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            for body in responses {
+                let p = s.next().await.unwrap().unwrap();
+                s.send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: p.id,
+                    body: body.to_owned(),
+                })
+                .await
+                .unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn cmd_parse_parses_numbers() {
+        let addr = scripted_server(vec![" 42\n", "There are 12 entities.", "1.5"]).await;
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd_parse::<u32, _>("count").await.unwrap(), 42);
+        assert_eq!(c.cmd_parse_first::<usize, _>("entities").await.unwrap(), 12);
+        assert_eq!(c.cmd_parse_first::<f64, _>("tps").await.unwrap(), 1.5);
+    }
+
+    #[tokio::test]
+    async fn cmd_parse_fails_on_text() {
+        let addr = scripted_server(vec!["Unknown command", "no numbers here"]).await;
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        match c.cmd_parse::<i32, _>("count").await {
+            Err(Error::Parse(body)) => assert_eq!(body, "Unknown command"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(
+            c.cmd_parse_first::<i32, _>("count").await,
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn auth_response_id_minus_one_is_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            s.next().await.unwrap().unwrap();
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: -1,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+        });
+
+        let err = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .expect_err("login should fail");
+        assert!(matches!(err, Error::AuthFailed), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_auth_failed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let auth = s.next().await.unwrap().unwrap();
+            let id = if auth.body == "secret" { auth.id } else { -1 };
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: auth.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+        });
+
+        let err = Connection::builder()
+            .connect(addr, "not the password")
+            .await
+            .expect_err("login should fail");
+        assert!(matches!(err, Error::AuthFailed));
+        assert_eq!(err.to_string(), "authentication failed: incorrect password");
+    }
+
+    #[tokio::test]
+    async fn profile_rewrites_commands() {
+        use crate::profile::CommandPrefix;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                accept_auth(&mut s).await;
+                echo(&mut s).await;
+            }
+        });
+
+        let mut c = Connection::builder()
+            .profile(ServerProfile::minecraft())
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("/list").await.unwrap(), "echo: list");
+
+        let mut c = Connection::builder()
+            .profile(ServerProfile::generic().command_prefix(CommandPrefix::Require("/".into())))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: /list");
+    }
+
+    #[tokio::test]
+    async fn run_once_closes_the_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            s.next().await.is_none()
+        });
+
+        assert_eq!(
+            run_once(addr, "password", "list").await.unwrap(),
+            "echo: list"
+        );
+        assert!(
+            server.await.unwrap(),
+            "the session should end after the command"
+        );
+    }
+
+    #[test]
+    fn packet_errors_keep_their_kind() {
+        let err = Error::from(PacketError::InvalidLength);
+        assert!(matches!(err, Error::Packet(PacketError::InvalidLength)));
+        assert_eq!(err.to_string(), "Packet Error: Invalid Packet Length");
+
+        let err = Error::from(PacketError::UndefinedType);
+        assert!(matches!(err, Error::Packet(PacketError::UndefinedType)));
+        assert_eq!(err.to_string(), "Packet Error: Undefined Packet Type");
+
+        let err = Error::from(PacketError::Io(IoError::from(ErrorKind::TimedOut)));
+        assert!(matches!(err, Error::Io(e) if e.kind() == ErrorKind::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn ping_measures_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            drop(s);
+
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .retry_delay(Duration::from_millis(1))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let rtt = c.ping().await.unwrap();
+        assert!(rtt > Duration::ZERO && rtt < Duration::from_secs(1));
+        // the server dropped the first connection, so this reconnects first
+        let rtt = c.ping().await.unwrap();
+        assert!(rtt > Duration::ZERO && rtt < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn connects_over_in_memory_transport() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut s = server_stream(server);
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::from_io(client, "password").await.unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+        // the transport is gone and can't be reopened
+        assert!(c.cmd("list").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversize_packets_fail_the_command() {
+        use tokio::io::AsyncWriteExt;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            s.next().await.unwrap().unwrap();
+            let mut oversize = 5000i32.to_le_bytes().to_vec();
+            oversize.resize(5004, 0);
+            s.get_mut().write_all(&oversize).await.unwrap();
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert!(matches!(
+            c.cmd("list").await,
+            Err(Error::Packet(PacketError::InvalidLength))
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_command_uses_profile_escaping() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .profile(ServerProfile::source())
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let cmd = Command::new("say").arg(r#"hi "there""#);
+        assert_eq!(
+            c.run_command(cmd).await.unwrap(),
+            r#"echo: say "hi 'there'""#
+        );
+    }
+
+    #[tokio::test]
+    async fn cmd_chunked_rejects_commands_longer_than_a_packet() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let err = c
+            .cmd_chunked("a".repeat(4087))
+            .await
+            .expect_err("the command doesn't fit in a packet");
+        assert!(matches!(
+            err,
+            Error::CommandTooLong {
+                len: 4097,
+                max: 4096
+            }
+        ));
+        assert!(err
+            .to_string()
+            .contains("4097 bytes, over the 4096 byte limit"));
+        let err = c.cmd_chunked("li\0st").await.unwrap_err();
+        assert!(matches!(err, Error::Command(CommandError::NulByte(2))));
+        assert_eq!(c.cmd_chunked("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn run_command_rejects_invalid_commands_before_sending() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let err = c
+            .run_command(Command::new("say").arg("a\0b"))
+            .await
+            .expect_err("a null byte should be rejected");
+        assert!(matches!(err, Error::Command(CommandError::NulByte(5))));
+        let err = c
+            .run_command(Command::new("say").arg("a".repeat(4096)))
+            .await
+            .expect_err("an over-length command should be rejected");
+        assert!(matches!(err, Error::Command(CommandError::TooLong(_))));
+        // neither was sent, so this is the first command the server sees
+        let cmd = Command::new("list");
+        assert_eq!(c.run_command(cmd).await.unwrap(), "echo: list");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn cmd_and_login_are_traced_in_spans() {
+        use std::sync::Mutex as StdMutex;
+        use tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id, Record},
+            Subscriber,
+        };
+        use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+        /// Collects `name field=value` for every span and recorded field.
+        struct Spans(Arc<StdMutex<Vec<String>>>);
+
+        struct Fields<'a>(&'a mut String);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                let mut s = attrs.metadata().name().to_owned();
+                attrs.record(&mut Fields(&mut s));
+                self.0.lock().unwrap().push(s);
+            }
+            fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+                let mut s = ctx.span(id).unwrap().name().to_owned();
+                values.record(&mut Fields(&mut s));
+                self.0.lock().unwrap().push(s);
+            }
+        }
+
+        let spans = Arc::new(StdMutex::new(Vec::new()));
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(Spans(Arc::clone(&spans))),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.cmd("list").await.unwrap();
+
+        let spans = spans.lock().unwrap();
+        let host = format!("host={}", addr);
+        assert!(spans
+            .iter()
+            .any(|s| s.starts_with("login") && s.contains(&host)));
+        assert!(spans.iter().any(|s| s.starts_with("login id=1")));
+        assert!(spans
+            .iter()
+            .any(|s| s.starts_with("cmd") && s.contains(&host)));
+        assert!(spans.iter().any(|s| s.starts_with("cmd id=2")));
+    }
+
+    #[tokio::test]
+    async fn pipelined_auth_is_sent_with_the_first_command() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            // nothing is answered until both packets have arrived
+            let auth = s.next().await.unwrap().unwrap();
+            assert_eq!(auth.ptype, PacketType::Auth);
+            let cmd = s.next().await.unwrap().unwrap();
+            assert_eq!(cmd.ptype, PacketType::ExecCommand);
+            for (ptype, id, body) in [
+                (PacketType::ResponseValue, auth.id, String::new()),
+                (PacketType::AuthResponse, auth.id, String::new()),
+                (
+                    PacketType::ResponseValue,
+                    cmd.id,
+                    format!("echo: {}", cmd.body),
+                ),
+            ] {
+                s.send(Packet { ptype, id, body }).await.unwrap();
+            }
+            echo(&mut s).await;
+        });
+
+        let mut c = tokio::time::timeout(
+            Duration::from_secs(5),
+            Connection::builder()
+                .pipeline_auth(true)
+                .connect(addr, "password"),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(!c.is_healthy());
+        assert_eq!(c.cmd("first").await.unwrap(), "echo: first");
+        assert_eq!(c.cmd("second").await.unwrap(), "echo: second");
+    }
+
+    #[tokio::test]
+    async fn pipelined_auth_failure_discards_the_command() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let auth = s.next().await.unwrap().unwrap();
+            let cmd = s.next().await.unwrap().unwrap();
+            for (ptype, id, body) in [
+                (PacketType::ResponseValue, auth.id, String::new()),
+                (PacketType::AuthResponse, -1, String::new()),
+                (PacketType::ResponseValue, cmd.id, "ran anyway".to_owned()),
+            ] {
+                s.send(Packet { ptype, id, body }).await.unwrap();
+            }
+        });
+
+        let mut c = Connection::builder()
+            .pipeline_auth(true)
+            .connect(addr, "wrong")
+            .await
+            .unwrap();
+        assert!(matches!(c.cmd("list").await, Err(Error::AuthFailed)));
+    }
+
+    #[test]
+    fn max_packet_length_reflects_the_builder() {
+        let c = Connection::builder().build(Endpoint::Io, "pass").unwrap();
+        assert_eq!(c.max_packet_length(), 4096);
+        let c = Connection::builder()
+            .max_packet_length(16384)
+            .build(Endpoint::Io, "pass")
+            .unwrap();
+        assert_eq!(c.max_packet_length(), 16384);
+        assert_eq!(c.clone_config().max_packet_length(), 16384);
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let c = Connection::builder()
+            .retry_delay(Duration::from_millis(100))
+            .exponential_backoff(true)
+            .build(Endpoint::Io, "pass")
+            .unwrap();
+        let delays: Vec<_> = (1..=4).map(|n| c.backoff_delay(n)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn backoff_jitter_range() {
+        let c = Connection::builder()
+            .retry_delay(Duration::from_millis(100))
+            .exponential_backoff(true)
+            .jitter(0.25)
+            .build(Endpoint::Io, "pass")
+            .unwrap();
+        let mut distinct = std::collections::HashSet::new();
+        for attempt in 1..=4 {
+            let base = 100.0 * 2f64.powi(attempt as i32 - 1);
+            for _ in 0..50 {
+                let d = c.backoff_delay(attempt);
+                let ms = d.as_secs_f64() * 1000.0;
+                assert!(
+                    (base * 0.75 - 0.001..=base * 1.25 + 0.001).contains(&ms),
+                    "{:?} outside of {}ms +-25%",
+                    d,
+                    base
+                );
+                distinct.insert(d);
+            }
+        }
+        assert!(distinct.len() > 4, "delays weren't randomized");
+    }
+
+    #[cfg(feature = "debug-ids")]
+    #[tokio::test]
+    async fn id_trace_records_matched_pair() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.cmd("list").await.unwrap();
+
+        let events: Vec<_> = c.id_trace().into_iter().map(|e| e.event).collect();
+        let (auth, cmd) = match events[..] {
+            [IdEvent::Sent { id: auth, .. }, IdEvent::Received { .. }, IdEvent::Sent { id: cmd, ptype }, IdEvent::Received { .. }] =>
+            {
+                assert_eq!(ptype, PacketType::ExecCommand);
+                (auth, cmd)
+            }
+            _ => panic!("unexpected trace {:?}", events),
+        };
+        assert_eq!(
+            events[3],
+            IdEvent::Received {
+                id: cmd,
+                ptype: PacketType::ResponseValue,
+                expected: cmd
+            }
+        );
+        assert_eq!(
+            events[1],
+            IdEvent::Received {
+                id: auth,
+                ptype: PacketType::AuthResponse,
+                expected: auth
+            }
+        );
+        assert!(c.id_trace()[3].is_match());
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn config_json_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let config = Connection::builder()
+            .max_retries(7)
+            .retry_delay(Duration::from_millis(5))
+            .command_timeout(Duration::from_secs(2))
+            .bind_address("127.0.0.1:0".parse().unwrap())
+            .backpressure(Backpressure::Wait)
+            .config()
+            .clone();
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded: ConnectionConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, config);
+
+        let mut c = Connection::from_config(loaded, addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.max_retries, 7);
+        assert_eq!(c.command_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_response_sets_needs_reauth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            // the password was rotated, so the server forgets the login
+            s.next().await.unwrap().unwrap();
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: -1,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+        assert!(!c.needs_reauth());
+
+        assert!(matches!(c.cmd("list").await, Err(Error::Unauthenticated)));
+        assert!(c.needs_reauth());
+        assert!(!c.is_healthy());
+
+        c.reauthenticate().await.unwrap();
+        assert!(!c.needs_reauth());
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn connect_any_fails_over_to_live_endpoint() {
+        let dead = closed_port().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let p = s.next().await.unwrap().unwrap();
+            assert_eq!(p.body, "fallback password");
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            echo(&mut s).await;
+            drop(s);
+
+            // reconnects stick to the endpoint which worked
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .max_retries(2)
+            .retry_delay(Duration::from_millis(1))
+            .connect_any(vec![
+                (dead, "primary password"),
+                (live, "fallback password"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(c.host.to_string(), live.to_string());
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+        assert_eq!(c.host.to_string(), live.to_string());
+    }
+
+    /// Sends `junk` empty responses before accepting the login.
+    async fn accept_auth_after(stream: &mut ServerStream, junk: i32) {
+        let p = stream.next().await.unwrap().unwrap();
+        for _ in 0..junk {
+            stream
+                .send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: p.id,
+                    body: String::new(),
+                })
+                .await
+                .unwrap();
+        }
+        stream
+            .send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_skips_packets_up_to_the_allowance() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth_after(&mut s, 2).await;
+            echo(&mut s).await;
+
+            let mut s = accept(&listener).await;
+            accept_auth_after(&mut s, 2).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+
+        let err = Connection::builder()
+            .auth_skip_packets(1)
+            .connect(addr, "password")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidResponse), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn response_stream_yields_pushed_bodies() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            for line in ["joined", "said hi", "left"] {
+                s.send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: 0,
+                    body: line.to_owned(),
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        let c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let lines: Vec<_> = c.into_response_stream().map(Result::unwrap).collect().await;
+        assert_eq!(lines, ["joined", "said hi", "left"]);
+    }
+
+    /// Passes everything through to `inner`, recording when the stream is shut down.
+    struct RecordShutdown<T> {
+        inner: T,
+        shut_down: Arc<AtomicBool>,
+    }
+
+    impl<T: AsyncRead + Unpin> AsyncRead for RecordShutdown<T> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> AsyncWrite for RecordShutdown<T> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.shut_down.store(true, Ordering::SeqCst);
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_shuts_down_the_stream() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut s = server_stream(server);
+        tokio::spawn(async move {
+            accept_auth(&mut s).await;
+            s.next().await
+        });
+        let shut_down = Arc::new(AtomicBool::new(false));
+        let io = RecordShutdown {
+            inner: client,
+            shut_down: shut_down.clone(),
+        };
+        let c = Connection::from_io(io, "password").await.unwrap();
+        assert!(!shut_down.load(Ordering::SeqCst));
+        drop(c);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !shut_down.load(Ordering::SeqCst) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the dropped connection's stream should be shut down");
+    }
+
+    #[tokio::test]
+    async fn drop_outside_a_runtime_doesnt_panic() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut s = server_stream(server);
+        tokio::spawn(async move { accept_auth(&mut s).await });
+        let c = Connection::from_io(client, "password").await.unwrap();
+        std::thread::spawn(move || drop(c)).join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_password_rejected_by_default() {
+        let addr = closed_port().await;
+        let err = Connection::builder()
+            .connect(addr, "")
+            .await
+            .expect_err("an empty password should be rejected");
+        assert!(matches!(err, Error::EmptyPassword));
+    }
+
+    #[tokio::test]
+    async fn empty_password_sent_when_allowed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let p = s.next().await.unwrap().unwrap();
+            assert_eq!((p.ptype, p.body.as_str()), (PacketType::Auth, ""));
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+        });
+
+        let c = Connection::builder()
+            .allow_empty_password(true)
+            .connect(addr, "")
+            .await
+            .unwrap();
+        assert!(c.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn cmd_with_id_returns_the_echoed_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sent_tx, sent_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            let p = s.next().await.unwrap().unwrap();
+            sent_tx.send(p.id).unwrap();
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: "done".to_owned(),
+            })
+            .await
+            .unwrap();
+        });
+
+        let mut c = Connection::builder()
+            .id_generator(|| 1234)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let (id, body) = c.cmd_with_id("save").await.unwrap();
+        assert_eq!(id, sent_rx.await.unwrap());
+        assert_eq!((id, body.as_str()), (1234, "done"));
+    }
+
+    #[tokio::test]
+    async fn connect_env_reads_password() {
+        const VAR: &str = "RCON_RS_TEST_CONNECT_ENV_PASSWORD";
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let p = s.next().await.unwrap().unwrap();
+            assert_eq!(p.body, "from the env");
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+        });
+
+        std::env::set_var(VAR, "from the env");
+        let c = Connection::builder().connect_env(addr, VAR).await.unwrap();
+        assert!(c.is_healthy());
+
+        std::env::remove_var(VAR);
+        let err = Connection::builder()
+            .connect_env(addr, VAR)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::MissingEnv(v) if v == VAR));
+    }
+
+    #[tokio::test]
+    async fn packet_observer_sees_login_and_command() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let mut c = Connection::builder()
+            .packet_observer(move |dir, p| log.lock().unwrap().push((dir, p.ptype, p.body.clone())))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        c.cmd("list").await.unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (Direction::Sent, PacketType::Auth, "password".to_owned()),
+                (Direction::Received, PacketType::AuthResponse, String::new()),
+                (Direction::Sent, PacketType::ExecCommand, "list".to_owned()),
+                (
+                    Direction::Received,
+                    PacketType::ResponseValue,
+                    "echo: list".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let c = Connection::builder()
+            .retry_delay(Duration::from_millis(10))
+            .exponential_backoff(true)
+            .jitter(0.5)
+            .max_retry_delay(Duration::from_millis(500))
+            .build(Endpoint::Io, "pass")
+            .unwrap();
+        for attempt in 1..=64 {
+            assert!(c.backoff_delay(attempt) <= Duration::from_millis(500));
+        }
+        assert!(c.backoff_delay(40) >= Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn reconnect_attempts_resolve_the_host_again() {
+        let dead = closed_port().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let lookups = Arc::new(AtomicI32::new(0));
+        let counter = Arc::clone(&lookups);
+        let mut c = Connection::builder()
+            .max_retries(3)
+            .retry_delay(Duration::from_millis(1))
+            .resolver(move |host| {
+                assert_eq!(host, "rcon.example:25575");
+                let first = counter.fetch_add(1, Ordering::SeqCst) == 0;
+                async move { Ok(vec![if first { dead } else { addr }]) }
+            })
+            .connect_host("rcon.example:25575", "password")
+            .await
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(c.cmd("one").await.unwrap(), "echo: one");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn debug_output_redacts_the_password() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+        });
+
+        let builder = Connection::builder().max_retries(2);
+        assert!(format!("{:?}", builder).contains("max_retries: 2"));
+        let c = builder.connect(addr, "hunter2").await.unwrap();
+        server.await.unwrap();
+        let debug = format!("{:?}", c);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert!(debug.contains("<redacted>") && debug.contains("authenticated: true"));
+    }
+
+    #[tokio::test]
+    async fn cmd_reports_a_closed_connection_then_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                accept_auth(&mut s).await;
+            }
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .retry_delay(Duration::from_millis(1))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert!(matches!(c.cmd("one").await, Err(Error::ConnectionClosed)));
+        assert!(!c.is_healthy());
+        assert_eq!(c.cmd("two").await.unwrap(), "echo: two");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cmd_batch_sends_everything_before_reading() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            let mut received = Vec::new();
+            for _ in 0..10 {
+                received.push(s.next().await.unwrap().unwrap());
+            }
+            // answer the commands in reverse, each followed by its terminator, splitting every
+            // real response across two packets
+            let order = received.chunks(2).rev().flatten().cloned();
+            for p in order {
+                let parts = if p.body.is_empty() {
+                    vec![String::new()]
+                } else {
+                    vec!["echo: ".to_owned(), p.body]
+                };
+                for body in parts {
+                    s.feed(Packet {
+                        ptype: PacketType::ResponseValue,
+                        id: p.id,
+                        body,
+                    })
+                    .await
+                    .unwrap();
+                }
+            }
+            s.flush().await.unwrap();
+        });
+
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let cmds: Vec<String> = (1..=5).map(|i| format!("cmd{}", i)).collect();
+        assert_eq!(
+            c.cmd_batch(cmds).await.unwrap(),
+            [
+                "echo: cmd1",
+                "echo: cmd2",
+                "echo: cmd3",
+                "echo: cmd4",
+                "echo: cmd5"
+            ]
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn try_login_reports_the_password_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                let auth = s.next().await.unwrap().unwrap();
+                let id = if auth.body == "secret" { auth.id } else { -1 };
+                s.send(Packet {
+                    ptype: PacketType::AuthResponse,
+                    id,
+                    body: String::new(),
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        for (password, accepted) in [("secret", true), ("wrong", false)] {
+            let mut c = Connection::builder()
+                .pipeline_auth(true)
+                .connect(addr, password)
+                .await
+                .unwrap();
+            assert_eq!(c.try_login().await.unwrap(), accepted, "{}", password);
+            assert_eq!(c.is_healthy(), accepted);
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_retries_transient_failures_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            // still starting up, hangs up without answering the login
+            let mut s = accept(&listener).await;
+            s.next().await.unwrap().unwrap();
+            drop(s);
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            listener
+        });
+        let mut c = Connection::builder()
+            .retry_delay(Duration::from_millis(1))
+            .auth_max_retries(2)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("one").await.unwrap(), "echo: one");
+        let listener = server.await.unwrap();
+
+        let accepts = Arc::new(AtomicI32::new(0));
+        let counter = Arc::clone(&accepts);
+        tokio::spawn(async move {
+            loop {
+                let mut s = accept(&listener).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let auth = s.next().await.unwrap().unwrap();
+                s.send(Packet {
+                    ptype: PacketType::AuthResponse,
+                    id: -1,
+                    body: auth.body,
+                })
+                .await
+                .unwrap();
+            }
+        });
+        let err = Connection::builder()
+            .retry_delay(Duration::from_millis(1))
+            .auth_max_retries(2)
+            .connect(addr, "wrong")
+            .await
+            .expect_err("a rejected password shouldn't be retried");
+        assert!(matches!(err, Error::AuthFailed));
+        assert_eq!(accepts.load(Ordering::SeqCst), 1);
+    }
+
+    /// Counts the flushes of the wrapped transport.
+    struct CountFlushes<T>(T, Arc<AtomicI32>);
+
+    impl<T: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for CountFlushes<T> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl<T: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for CountFlushes<T> {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::pin::Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            std::pin::Pin::new(&mut self.0).poll_flush(cx)
+        }
+        fn poll_shutdown(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn cmd_batch_flushes_once() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut s = server_stream(server);
+            accept_auth(&mut s).await;
+            for _ in 0..6 {
+                echo(&mut s).await;
+            }
+        });
+
+        let flushes = Arc::new(AtomicI32::new(0));
+        let io = CountFlushes(client, Arc::clone(&flushes));
+        let mut c = Connection::from_io(io, "password").await.unwrap();
+        let before = flushes.load(Ordering::SeqCst);
+        let cmds = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        assert_eq!(
+            c.cmd_batch(cmds).await.unwrap(),
+            ["echo: a", "echo: b", "echo: c"]
+        );
+        assert_eq!(flushes.load(Ordering::SeqCst) - before, 1);
+        c.flush().await.unwrap();
+        assert_eq!(flushes.load(Ordering::SeqCst) - before, 2);
+    }
+
+    #[tokio::test]
+    async fn auth_id_is_sent_as_configured() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let auth = s.next().await.unwrap().unwrap();
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: auth.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            auth.id
+        });
+
+        Connection::builder()
+            .auth_id(Some(4242))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(server.await.unwrap(), 4242);
+
+        let err = Connection::builder()
+            .auth_id(Some(-1))
+            .connect(addr, "password")
+            .await
+            .expect_err("-1 can't be used as the auth id");
+        assert!(matches!(err, Error::InvalidAuthId));
+    }
+
+    #[tokio::test]
+    async fn try_recv_returns_pushed_packets_without_waiting() {
+        let (client, server) = tokio::io::duplex(4096);
+        let (pushed_tx, pushed_rx) = tokio::sync::oneshot::channel();
+        let (push_tx, push_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut s = server_stream(server);
+            accept_auth(&mut s).await;
+            push_rx.await.unwrap();
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: 0,
+                body: "player joined".to_owned(),
+            })
+            .await
+            .unwrap();
+            pushed_tx.send(()).unwrap();
+            drop(s);
+        });
+
+        let mut c = Connection::from_io(client, "password").await.unwrap();
+        assert!(c.try_recv().unwrap().is_none());
+        push_tx.send(()).unwrap();
+        pushed_rx.await.unwrap();
+        assert_eq!(c.try_recv().unwrap().as_deref(), Some("player joined"));
+        // the server has hung up since
+        tokio::task::yield_now().await;
+        assert!(matches!(c.try_recv(), Err(Error::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn empty_response_differs_from_closed_connection() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut s = server_stream(server);
+            accept_auth(&mut s).await;
+            let p = s.next().await.unwrap().unwrap();
+            s.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            // hang up in the middle of the next command
+            s.next().await.unwrap().unwrap();
+        });
+
+        let mut c = Connection::from_io(client, "password").await.unwrap();
+        assert_eq!(c.cmd("say").await.unwrap(), "");
+        assert!(matches!(c.cmd("list").await, Err(Error::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn minecraft_kind_skips_the_extra_packet() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                let auth = s.next().await.unwrap().unwrap();
+                for ptype in [PacketType::AuthResponse, PacketType::ResponseValue] {
+                    let p = Packet {
+                        ptype,
+                        id: auth.id,
+                        body: String::new(),
+                    };
+                    s.send(p).await.unwrap();
+                }
+                echo(&mut s).await;
+            }
+        });
+
+        let mut generic = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(generic.cmd("list").await.unwrap(), "");
+
+        let mut minecraft = Connection::builder()
+            .server_kind(ServerKind::Minecraft)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(minecraft.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn server_kind_sets_the_packets_allowed_before_auth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut s = accept(&listener).await;
+                accept_auth_after(&mut s, 1).await;
+            }
+        });
+
+        for (kind, ok) in [(ServerKind::Source, true), (ServerKind::Minecraft, false)] {
+            let res = Connection::builder()
+                .server_kind(kind)
+                .connect(addr, "password")
+                .await;
+            assert_eq!(res.is_ok(), ok, "{:?}", kind);
+        }
+    }
+
+    #[tokio::test]
+    async fn round_robin_dns_moves_through_the_cached_addresses() {
+        let dead = closed_port().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+        });
+
+        let lookups = Arc::new(AtomicI32::new(0));
+        let counter = Arc::clone(&lookups);
+        Connection::builder()
+            .max_retries(4)
+            .retry_delay(Duration::from_millis(1))
+            .round_robin_dns(true)
+            .resolver(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move { Ok(vec![dead, addr]) }
+            })
+            .connect_host("rcon.example:25575", "password")
+            .await
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn connects_to_a_host_and_port_tuple() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::builder()
+            .connect(("127.0.0.1", port), "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn dns_ttl_limits_resolving_again() {
+        let dead = closed_port().await;
+        for (ttl, expected) in [
+            (Duration::from_secs(3600), 1),
+            (Duration::from_millis(10), 2),
+        ] {
+            let lookups = Arc::new(AtomicI32::new(0));
+            let counter = Arc::clone(&lookups);
+            let res = Connection::builder()
+                .max_retries(3)
+                .retry_delay(Duration::from_millis(30))
+                .dns_ttl(ttl)
+                .resolver(move |_| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async move { Ok(vec![dead]) }
+                })
+                .connect_host("rcon.example:25575", "password")
+                .await;
+            assert!(res.is_err());
+            assert_eq!(lookups.load(Ordering::SeqCst), expected, "ttl {:?}", ttl);
+        }
+    }
+
+    #[tokio::test]
+    async fn cmd_bytes_round_trips_invalid_utf8() {
+        let (client, server) = tokio::io::duplex(4096);
+        let (sent_tx, sent_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let mut s = server_stream(server);
+            accept_auth(&mut s).await;
+            // echo the next packet's bytes back as its response
+            let mut io = s.into_inner();
+            let mut len = [0; 4];
+            io.read_exact(&mut len).await.unwrap();
+            let mut frame = vec![0; i32::from_le_bytes(len) as usize];
+            io.read_exact(&mut frame).await.unwrap();
+            sent_tx.send(frame[8..].to_vec()).unwrap();
+            frame[4..8].copy_from_slice(&0i32.to_le_bytes());
+            io.write_all(&len).await.unwrap();
+            io.write_all(&frame).await.unwrap();
+            let mut s = server_stream(io);
+            echo(&mut s).await;
+        });
+
+        let mut c = Connection::from_io(client, "password").await.unwrap();
+        let body = vec![b's', b'a', b'y', b' ', 0x80, b'!'];
+        assert_eq!(c.cmd_bytes(body.clone()).await.unwrap(), body);
+        assert_eq!(sent_rx.await.unwrap(), [&body[..], &[0, 0]].concat());
+        // the codec carries on from where the raw exchange left off
+        assert_eq!(c.cmd("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn cmd_bytes_goes_through_the_codec() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let mut c = Connection::builder()
+            .max_packet_length(64)
+            .packet_observer(move |dir, p| log.lock().unwrap().push((dir, p.body.clone())))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        seen.lock().unwrap().clear();
+        assert_eq!(c.cmd_bytes(b"list".to_vec()).await.unwrap(), b"echo: list");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (Direction::Sent, "list".to_owned()),
+                (Direction::Received, "echo: list".to_owned()),
+            ]
+        );
+
+        let err = c.cmd_bytes(vec![0x80; 64]).await.unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == ErrorKind::InvalidInput));
+    }
+}
@@ -0,0 +1,131 @@
+use super::{response::DEFAULT_ERROR_HINTS, Backpressure, Protocol, ServerKind};
+use std::{net::SocketAddr, time::Duration};
+
+/// The plain settings of a [`Builder`](super::Builder), kept apart from the fluent API so they can be
+/// stored and versioned. With the `serde` feature it can be loaded from JSON, TOML or any other format,
+/// missing fields fall back to their defaults and durations are written as `{ secs, nanos }`.
+/// The id generator, server profile and proxy aren't included.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ConnectionConfig {
+    /// See [`Builder::max_retries`](super::Builder::max_retries).
+    pub max_retries: u32,
+    /// See [`Builder::retry_delay`](super::Builder::retry_delay).
+    pub retry_delay: Duration,
+    /// See [`Builder::exponential_backoff`](super::Builder::exponential_backoff).
+    pub exponential_backoff: bool,
+    /// See [`Builder::jitter`](super::Builder::jitter).
+    pub jitter: f64,
+    /// See [`Builder::validate_password`](super::Builder::validate_password).
+    pub validate_password: bool,
+    /// See [`Builder::allow_empty_password`](super::Builder::allow_empty_password).
+    pub allow_empty_password: bool,
+    /// See [`Builder::structured_errors`](super::Builder::structured_errors).
+    pub structured_errors: bool,
+    /// See [`Builder::slow_command_threshold`](super::Builder::slow_command_threshold).
+    pub slow_command_threshold: Option<Duration>,
+    /// See [`Builder::max_packet_length`](super::Builder::max_packet_length).
+    pub max_packet_length: usize,
+    /// See [`Builder::bind_address`](super::Builder::bind_address).
+    pub bind_address: Option<SocketAddr>,
+    /// See [`Builder::tcp_nodelay`](super::Builder::tcp_nodelay).
+    pub tcp_nodelay: bool,
+    /// See [`Builder::keepalive_interval`](super::Builder::keepalive_interval).
+    pub keepalive_interval: Option<Duration>,
+    /// See [`Builder::auto_reconnect`](super::Builder::auto_reconnect).
+    pub auto_reconnect: bool,
+    /// See [`Builder::command_timeout`](super::Builder::command_timeout).
+    pub command_timeout: Option<Duration>,
+    /// See [`Builder::max_pending_requests`](super::Builder::max_pending_requests).
+    pub max_pending_requests: Option<usize>,
+    /// See [`Builder::backpressure`](super::Builder::backpressure).
+    pub backpressure: Backpressure,
+    /// See [`Builder::pipeline_auth`](super::Builder::pipeline_auth).
+    pub pipeline_auth: bool,
+    /// See [`Builder::protocol`](super::Builder::protocol).
+    pub protocol: Protocol,
+    /// See [`Builder::auth_skip_packets`](super::Builder::auth_skip_packets).
+    pub auth_skip_packets: usize,
+    /// See [`Builder::max_retry_delay`](super::Builder::max_retry_delay).
+    pub max_retry_delay: Duration,
+    /// See [`Builder::round_robin_dns`](super::Builder::round_robin_dns).
+    pub round_robin_dns: bool,
+    /// See [`Builder::auth_max_retries`](super::Builder::auth_max_retries).
+    pub auth_max_retries: u32,
+    /// See [`Builder::auth_id`](super::Builder::auth_id).
+    pub auth_id: Option<i32>,
+    /// See [`Builder::server_kind`](super::Builder::server_kind).
+    pub server_kind: ServerKind,
+    /// See [`Builder::error_hints`](super::Builder::error_hints).
+    pub error_hints: Vec<String>,
+    /// See [`Builder::dns_ttl`](super::Builder::dns_ttl).
+    pub dns_ttl: Duration,
+    /// See [`Builder::tcp_keepalive`](super::Builder::tcp_keepalive).
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            max_retries: 3,
+            retry_delay: Duration::from_millis(1000),
+            exponential_backoff: false,
+            jitter: 0.0,
+            validate_password: false,
+            allow_empty_password: false,
+            structured_errors: false,
+            slow_command_threshold: None,
+            max_packet_length: 4096,
+            bind_address: None,
+            tcp_nodelay: true,
+            keepalive_interval: None,
+            auto_reconnect: false,
+            command_timeout: None,
+            max_pending_requests: None,
+            backpressure: Backpressure::Fail,
+            pipeline_auth: false,
+            protocol: Protocol::Source,
+            auth_skip_packets: 3,
+            max_retry_delay: Duration::from_secs(30),
+            round_robin_dns: false,
+            auth_max_retries: 0,
+            auth_id: None,
+            server_kind: ServerKind::Generic,
+            error_hints: DEFAULT_ERROR_HINTS.iter().map(|h| h.to_string()).collect(),
+            dns_ttl: Duration::ZERO,
+            tcp_keepalive: None,
+        }
+    }
+}
+
+/// Clamps a jitter fraction to `0.0..=1.0`, treating NaN as no jitter.
+pub(super) fn clamp_jitter(fraction: f64) -> f64 {
+    if fraction.is_nan() {
+        0.0
+    } else {
+        fraction.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_default() {
+        let config: ConnectionConfig =
+            serde_json::from_str(r#"{"max_retries": 5, "protocol": "BattlEye"}"#).unwrap();
+        assert_eq!(
+            config,
+            ConnectionConfig {
+                max_retries: 5,
+                protocol: Protocol::BattlEye,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,740 @@
+use super::{
+    observe, persistent::Permanent, Backpressure, Connection, Direction, Error, PacketObserver,
+    Protocol, Result,
+};
+use crate::packet::{Packet, PacketType};
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace};
+use std::io::{Error as IoError, ErrorKind};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{oneshot, Mutex, MutexGuard, Semaphore},
+    time::{sleep, timeout, Instant},
+};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace};
+
+type Transport = super::Stream;
+type Pending = StdMutex<HashMap<i32, oneshot::Sender<Result<Packet>>>>;
+
+/// A clonable handle for running commands concurrently over a single connection.
+/// A background task reads every response and routes it to the caller waiting on its packet id.
+/// Created with [`Connection::handle`].
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    config: Connection,
+    link: Mutex<Option<Arc<Link>>>,
+    /// The liveness flag of the current link, readable without waiting on `link`
+    alive: StdMutex<Arc<AtomicBool>>,
+    /// Limits the number of pending requests, if `max_pending_requests` is set
+    slots: Option<Semaphore>,
+}
+
+/// An authenticated connection with a running reader task.
+struct Link {
+    sink: Mutex<SplitSink<Transport, Packet>>,
+    pending: Arc<Pending>,
+    alive: Arc<AtomicBool>,
+    observer: Option<PacketObserver>,
+}
+
+impl ConnectionHandle {
+    pub(super) fn new(config: Connection) -> ConnectionHandle {
+        ConnectionHandle {
+            shared: Arc::new(Shared {
+                slots: config.max_pending_requests.map(Semaphore::new),
+                config,
+                link: Mutex::new(None),
+                alive: StdMutex::new(Arc::new(AtomicBool::new(false))),
+            }),
+        }
+    }
+
+    /// Sends a command, connecting first if needed, and waits for its response.
+    pub async fn cmd<C: ToString>(&self, cmd: C) -> Result<String> {
+        let config = &self.shared.config;
+        let cmd = config.profile.normalize(&cmd.to_string()).into_owned();
+        debug!("running command: \"{}\"", &cmd);
+        let start = Instant::now();
+
+        let request = async {
+            let _permit = match &self.shared.slots {
+                Some(s) if config.backpressure == Backpressure::Wait => {
+                    Some(s.acquire().await.expect("semaphore is never closed"))
+                }
+                Some(s) => Some(s.try_acquire().map_err(|_| Error::Busy)?),
+                None => None,
+            };
+            let link = self.link().await?;
+            link.request(config.next_id(), cmd.clone()).await
+        };
+        let p = match config.command_timeout {
+            Some(t) => timeout(t, request)
+                .await
+                .map_err(|_| Error::Io(IoError::new(ErrorKind::TimedOut, "command timed out")))??,
+            None => request.await?,
+        };
+        config.warn_if_slow(&cmd, start);
+        config.response_body(p.body)
+    }
+
+    /// Closes the connection, if one is open, and stops the keepalive task.
+    /// The next command reconnects.
+    pub async fn close(&self) -> Result<()> {
+        if let Some(l) = self.shared.link.lock().await.take() {
+            trace!("closing handle connection to {}", self.shared.config.host);
+            l.alive.store(false, Ordering::Release);
+            l.sink.lock().await.close().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the background reader is running on an authenticated connection.
+    /// This is false before the first command connects the handle.
+    pub fn is_healthy(&self) -> bool {
+        self.shared.alive.lock().unwrap().load(Ordering::Acquire)
+    }
+
+    /// Gets the current link, connecting and logging in if there isn't a live one.
+    /// With `auto_reconnect` failed attempts are retried up to `max_retries` times, unless retrying can't help.
+    async fn link(&self) -> Result<Arc<Link>> {
+        let config = &self.shared.config;
+        let mut attempt = 0;
+        loop {
+            let mut link = self.shared.link.lock().await;
+            if let Some(l) = link.as_ref() {
+                if l.alive.load(Ordering::Acquire) {
+                    return Ok(Arc::clone(l));
+                }
+            }
+            match self.relink(&mut link).await {
+                Ok(l) => return Ok(l),
+                Err(e)
+                    if config.auto_reconnect && attempt < config.max_retries && retryable(&e) =>
+                {
+                    // not held while waiting, so closing the handle isn't held up
+                    drop(link);
+                    attempt += 1;
+                    let delay = config.backoff_delay(attempt);
+                    debug!(
+                        "failed to reconnect handle, retrying #{} in {:?}: {}",
+                        attempt, delay, e
+                    );
+                    sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Opens a new link in place of the current one.
+    async fn relink(&self, link: &mut MutexGuard<'_, Option<Arc<Link>>>) -> Result<Arc<Link>> {
+        if self.shared.config.protocol != Protocol::Source {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::Unsupported,
+                "handles only support the Source protocol",
+            )));
+        }
+        trace!("connecting handle to {}", self.shared.config.host);
+        let mut c = self.shared.config.clone_config();
+        c.connect().await?;
+        c.login().await?;
+        let stream = c.stream.take().expect("connected after login");
+        let (sink, stream) = stream.split();
+
+        let l = Arc::new(Link {
+            sink: Mutex::new(sink),
+            pending: Arc::new(StdMutex::new(HashMap::new())),
+            alive: Arc::new(AtomicBool::new(true)),
+            observer: c.packet_observer.clone(),
+        });
+        let reader = tokio::spawn(read_responses(
+            stream,
+            Arc::clone(&l.pending),
+            Arc::clone(&l.alive),
+            c.packet_observer.clone(),
+        ));
+        if self.shared.config.auto_reconnect {
+            let shared = Arc::downgrade(&self.shared);
+            let dead = Arc::downgrade(&l);
+            tokio::spawn(async move {
+                let _ = reader.await;
+                reconnect(shared, dead).await;
+            });
+        }
+        if let Some(interval) = self.shared.config.keepalive_interval {
+            tokio::spawn(keepalive(
+                Arc::downgrade(&l),
+                self.shared.config.clone_config(),
+                interval,
+            ));
+        }
+        *self.shared.alive.lock().unwrap() = Arc::clone(&l.alive);
+        **link = Some(Arc::clone(&l));
+        Ok(l)
+    }
+}
+
+/// Whether connecting again might get past `e`, unlike a rejected password or an unsupported protocol.
+fn retryable(e: &Error) -> bool {
+    let unsupported = matches!(e, Error::Io(io) if io.kind() == ErrorKind::Unsupported);
+    !unsupported && Permanent::from_error(e).is_none()
+}
+
+/// Replaces a link whose reader has exited, unless it was closed or already replaced.
+/// This makes a single attempt under the retry policy, if it fails the next command keeps trying.
+/// Boxed since it is spawned from `relink`, which it calls in turn.
+fn reconnect(shared: Weak<Shared>, dead: Weak<Link>) -> BoxFuture<'static, ()> {
+    async move {
+        let handle = match shared.upgrade() {
+            Some(shared) => ConnectionHandle { shared },
+            None => return,
+        };
+        let mut link = handle.shared.link.lock().await;
+        if !link
+            .as_ref()
+            .is_some_and(|l| Weak::ptr_eq(&Arc::downgrade(l), &dead))
+        {
+            return;
+        }
+        debug!("handle connection dropped, reconnecting");
+        if let Err(e) = handle.relink(&mut link).await {
+            debug!("failed to reconnect handle: {}", e);
+        }
+    }
+    .boxed()
+}
+
+/// Removes a request from the pending map when it stops waiting, so requests which time out
+/// or are cancelled before their response arrives don't leave their id behind.
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: i32,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Link {
+    /// Sends a command and waits for the response with the same id.
+    async fn request(&self, id: i32, body: String) -> Result<Packet> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            id,
+        };
+        // the reader marks the link dead before failing pending requests, so a request
+        // registered after that would never be woken
+        if !self.alive.load(Ordering::Acquire) {
+            return Err(Error::Disconnected);
+        }
+
+        let pk = Packet {
+            ptype: PacketType::ExecCommand,
+            id,
+            body,
+        };
+        observe(&self.observer, Direction::Sent, &pk);
+        if let Err(e) = self.sink.lock().await.send(pk).await {
+            return Err(Error::Io(e));
+        }
+        rx.await.map_err(|_| Error::Disconnected)?
+    }
+}
+
+/// Pings the link every `interval` until it is closed, replaced or dies.
+/// Only a weak reference is held so dropping every handle also stops the pings.
+async fn keepalive(link: Weak<Link>, config: Connection, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let link = match link.upgrade() {
+            Some(l) if l.alive.load(Ordering::Acquire) => l,
+            _ => break,
+        };
+        trace!("sending keepalive to {}", config.host);
+        if let Err(e) = link.request(config.next_id(), String::new()).await {
+            debug!("keepalive failed: {}", e);
+            break;
+        }
+    }
+    trace!("keepalive finished");
+}
+
+/// Routes responses to the pending request with the same id until the connection ends.
+async fn read_responses(
+    mut stream: SplitStream<Transport>,
+    pending: Arc<Pending>,
+    alive: Arc<AtomicBool>,
+    observer: Option<PacketObserver>,
+) {
+    while let Some(res) = stream.next().await {
+        match res {
+            Ok(p) => {
+                observe(&observer, Direction::Received, &p);
+                match pending.lock().unwrap().remove(&p.id) {
+                    Some(tx) => {
+                        let _ = tx.send(Ok(p));
+                    }
+                    None => trace!("dropping response with unknown id {}", p.id),
+                }
+            }
+            Err(e) => {
+                debug!("failed to decode response: {}", e);
+                break;
+            }
+        }
+    }
+    trace!("handle reader finished");
+    // the next command sees the dead link and reconnects
+    alive.store(false, Ordering::Release);
+    for (_, tx) in pending.lock().unwrap().drain() {
+        let _ = tx.send(Err(Error::Disconnected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{accept, accept_auth, echo, server_stream};
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn concurrent_commands_are_routed_by_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            // answer in reverse order so responses only match up if they are routed by id
+            let mut requests = Vec::new();
+            for _ in 0..10 {
+                requests.push(h.next().await.unwrap().unwrap());
+            }
+            for p in requests.into_iter().rev() {
+                h.send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: p.id,
+                    body: format!("echo: {}", p.body),
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        let c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { (i, handle.cmd(format!("cmd {}", i)).await) })
+            })
+            .collect();
+        for t in tasks {
+            let (i, res) = t.await.unwrap();
+            assert_eq!(res.unwrap(), format!("echo: cmd {}", i));
+        }
+    }
+
+    #[tokio::test]
+    async fn pending_commands_fail_when_the_server_drops() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            for _ in 0..5 {
+                h.next().await.unwrap().unwrap();
+            }
+            // drop mid-flight without answering anything
+            drop(h);
+            let _a = a;
+            std::future::pending::<()>().await;
+        });
+
+        let c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let tasks: Vec<_> = (0..5)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.cmd(format!("cmd {}", i)).await })
+            })
+            .collect();
+        for t in tasks {
+            let res = timeout(Duration::from_secs(5), t).await.unwrap().unwrap();
+            assert!(matches!(res, Err(Error::Disconnected)), "{:?}", res);
+        }
+    }
+
+    #[tokio::test]
+    async fn is_healthy_tracks_the_reader() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (drop_tx, drop_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            echo(&mut h).await;
+            drop_rx.await.unwrap();
+            drop(h);
+            let _a = a;
+            std::future::pending::<()>().await;
+        });
+
+        let c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert!(c.is_healthy());
+        let handle = c.handle();
+        assert!(!handle.is_healthy());
+        handle.cmd("list").await.unwrap();
+        assert!(handle.is_healthy());
+
+        drop_tx.send(()).unwrap();
+        timeout(Duration::from_secs(5), async {
+            while handle.is_healthy() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn keepalive_pings_on_an_interval() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ping_tx, mut ping_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            while let Some(Ok(p)) = h.next().await {
+                if p.body.is_empty() {
+                    ping_tx.send(()).unwrap();
+                }
+                h.send(Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: p.id,
+                    body: format!("echo: {}", p.body),
+                })
+                .await
+                .unwrap();
+            }
+            let _a = a;
+        });
+
+        let c = Connection::builder()
+            .keepalive_interval(Some(Duration::from_millis(10)))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        assert_eq!(handle.cmd("list").await.unwrap(), "echo: list");
+        for _ in 0..3 {
+            timeout(Duration::from_secs(5), ping_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        // pings don't steal responses from commands
+        assert_eq!(handle.cmd("list").await.unwrap(), "echo: list");
+
+        handle.close().await.unwrap();
+        assert!(!handle.is_healthy());
+        // let any ping already in flight land before checking no more are sent
+        sleep(Duration::from_millis(50)).await;
+        while ping_rx.try_recv().is_ok() {}
+        sleep(Duration::from_millis(50)).await;
+        assert!(ping_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn auto_reconnect_holds_commands_until_the_server_returns() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (restart_tx, restart_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            echo(&mut h).await;
+            restart_rx.await.unwrap();
+            // go down for a while before coming back on the same address
+            drop(h);
+            drop(a);
+            drop(listener);
+            sleep(Duration::from_millis(200)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            echo(&mut h).await;
+            std::future::pending::<()>().await;
+        });
+
+        let c = Connection::builder()
+            .max_retries(100)
+            .retry_delay(Duration::from_millis(10))
+            .auto_reconnect(true)
+            .command_timeout(Duration::from_secs(5))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        assert_eq!(handle.cmd("before").await.unwrap(), "echo: before");
+
+        restart_tx.send(()).unwrap();
+        timeout(Duration::from_secs(5), async {
+            while handle.is_healthy() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(handle.cmd("after").await.unwrap(), "echo: after");
+    }
+
+    #[tokio::test]
+    async fn auto_reconnect_stops_when_the_password_is_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut logins = 0;
+            // the handle's logins are rejected, until it stops trying
+            while let Ok(Ok((socket, _))) =
+                timeout(Duration::from_millis(500), listener.accept()).await
+            {
+                logins += 1;
+                let mut h = server_stream(socket);
+                h.next().await.unwrap().unwrap();
+                h.send(Packet {
+                    ptype: PacketType::AuthResponse,
+                    id: -1,
+                    body: String::new(),
+                })
+                .await
+                .unwrap();
+            }
+            logins
+        });
+
+        let c = Connection::builder()
+            .retry_delay(Duration::from_millis(10))
+            .auto_reconnect(true)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let res = timeout(Duration::from_secs(5), handle.cmd("list")).await;
+        assert!(matches!(res.unwrap(), Err(Error::AuthFailed)));
+        timeout(Duration::from_secs(1), handle.close())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(server.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn command_timeout_fails_slow_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut a = accept(&listener).await;
+            accept_auth(&mut a).await;
+            let mut h = accept(&listener).await;
+            accept_auth(&mut h).await;
+            // never answer
+            let _ = h.next().await;
+            std::future::pending::<()>().await;
+        });
+
+        let c = Connection::builder()
+            .command_timeout(Duration::from_millis(50))
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        match handle.cmd("list").await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+            r => panic!("expected a timeout, got {:?}", r),
+        }
+        // the timed out request doesn't stay in the pending map
+        let link = handle.shared.link.lock().await.clone().unwrap();
+        assert!(link.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_commands_leave_no_pending_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received_tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let (_release, release_rx) = oneshot::channel();
+        tokio::spawn(held_server(listener, received_tx, release_rx));
+
+        let c = Connection::builder()
+            .max_pending_requests(1)
+            .backpressure(Backpressure::Wait)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let tasks: Vec<_> = (0..2)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.cmd(format!("cmd {}", i)).await })
+            })
+            .collect();
+        received.recv().await.unwrap();
+        // one task is waiting on its response and the other on a slot
+        for t in &tasks {
+            t.abort();
+        }
+        for t in tasks {
+            let _ = t.await;
+        }
+        let link = handle.shared.link.lock().await.clone().unwrap();
+        assert!(link.pending.lock().unwrap().is_empty());
+    }
+
+    /// Accepts a handle connection and reports each command it reads, answering them once `release` fires.
+    async fn held_server(
+        listener: TcpListener,
+        received: tokio::sync::mpsc::UnboundedSender<()>,
+        release: oneshot::Receiver<()>,
+    ) {
+        let mut a = accept(&listener).await;
+        accept_auth(&mut a).await;
+        let mut h = accept(&listener).await;
+        accept_auth(&mut h).await;
+        let mut held = Vec::new();
+        for _ in 0..2 {
+            held.push(h.next().await.unwrap().unwrap());
+            received.send(()).unwrap();
+        }
+        release.await.unwrap();
+        for p in held {
+            h.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: format!("echo: {}", p.body),
+            })
+            .await
+            .unwrap();
+        }
+        while let Some(Ok(p)) = h.next().await {
+            received.send(()).unwrap();
+            h.send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: p.id,
+                body: format!("echo: {}", p.body),
+            })
+            .await
+            .unwrap();
+        }
+        let _a = a;
+    }
+
+    #[tokio::test]
+    async fn full_pending_map_fails_with_busy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received_tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let (release, release_rx) = oneshot::channel();
+        tokio::spawn(held_server(listener, received_tx, release_rx));
+
+        let c = Connection::builder()
+            .max_pending_requests(2)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let tasks: Vec<_> = (0..2)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.cmd(format!("cmd {}", i)).await })
+            })
+            .collect();
+        for _ in 0..2 {
+            received.recv().await.unwrap();
+        }
+        assert!(matches!(handle.cmd("over").await, Err(Error::Busy)));
+
+        release.send(()).unwrap();
+        for t in tasks {
+            t.await.unwrap().unwrap();
+        }
+        // slots are freed once responses arrive
+        assert_eq!(handle.cmd("after").await.unwrap(), "echo: after");
+    }
+
+    #[tokio::test]
+    async fn full_pending_map_waits_for_a_slot() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received_tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let (release, release_rx) = oneshot::channel();
+        tokio::spawn(held_server(listener, received_tx, release_rx));
+
+        let c = Connection::builder()
+            .max_pending_requests(2)
+            .backpressure(Backpressure::Wait)
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        let handle = c.handle();
+        let tasks: Vec<_> = (0..3)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.cmd(format!("cmd {}", i)).await })
+            })
+            .collect();
+        for _ in 0..2 {
+            received.recv().await.unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+        // the third command is held back until a slot frees up
+        assert!(received.try_recv().is_err());
+
+        release.send(()).unwrap();
+        for t in tasks {
+            t.await.unwrap().unwrap();
+        }
+    }
+}
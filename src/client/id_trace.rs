@@ -0,0 +1,121 @@
+use crate::packet::Packet;
+#[cfg(feature = "debug-ids")]
+use crate::packet::PacketType;
+#[cfg(feature = "debug-ids")]
+use std::{collections::VecDeque, time::Instant};
+
+/// The number of events kept, older ones are dropped first.
+#[cfg(feature = "debug-ids")]
+const CAPACITY: usize = 256;
+
+/// A packet id sent or received by a [`Connection`](super::Connection), see [`Connection::id_trace`](super::Connection::id_trace).
+#[cfg(feature = "debug-ids")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdTraceEntry {
+    /// When the packet was sent or read.
+    pub at: Instant,
+    /// What happened.
+    pub event: IdEvent,
+}
+
+#[cfg(feature = "debug-ids")]
+impl IdTraceEntry {
+    /// Whether this is a response carrying the id of the request it was read for.
+    pub fn is_match(&self) -> bool {
+        matches!(self.event, IdEvent::Received { id, expected, .. } if id == expected)
+    }
+}
+
+/// The kind of [`IdTraceEntry`].
+#[cfg(feature = "debug-ids")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdEvent {
+    /// A request was written to the server.
+    Sent {
+        /// The id of the request.
+        id: i32,
+        /// The type of the request.
+        ptype: PacketType,
+    },
+    /// A packet was read while waiting for the response to a request.
+    Received {
+        /// The id the server sent back.
+        id: i32,
+        /// The type of the packet.
+        ptype: PacketType,
+        /// The id of the request being waited on.
+        expected: i32,
+    },
+}
+
+/// A capped log of the ids on a connection, which records nothing without the `debug-ids` feature.
+#[derive(Default)]
+pub(crate) struct IdTrace {
+    #[cfg(feature = "debug-ids")]
+    entries: VecDeque<IdTraceEntry>,
+}
+
+impl IdTrace {
+    pub(crate) fn sent(&mut self, _p: &Packet) {
+        #[cfg(feature = "debug-ids")]
+        self.push(IdEvent::Sent {
+            id: _p.id,
+            ptype: _p.ptype,
+        });
+    }
+
+    pub(crate) fn received(&mut self, _p: &Packet, _expected: i32) {
+        #[cfg(feature = "debug-ids")]
+        self.push(IdEvent::Received {
+            id: _p.id,
+            ptype: _p.ptype,
+            expected: _expected,
+        });
+    }
+
+    #[cfg(feature = "debug-ids")]
+    fn push(&mut self, event: IdEvent) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(IdTraceEntry {
+            at: Instant::now(),
+            event,
+        });
+    }
+
+    #[cfg(feature = "debug-ids")]
+    pub(crate) fn entries(&self) -> Vec<IdTraceEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(all(test, feature = "debug-ids"))]
+mod tests {
+    use super::*;
+
+    fn packet(id: i32) -> Packet {
+        Packet {
+            ptype: PacketType::ExecCommand,
+            id,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn capped() {
+        let mut trace = IdTrace::default();
+        for id in 0..CAPACITY as i32 + 10 {
+            trace.sent(&packet(id));
+        }
+        let entries = trace.entries();
+        assert_eq!(entries.len(), CAPACITY);
+        assert_eq!(
+            entries[0].event,
+            IdEvent::Sent {
+                id: 10,
+                ptype: PacketType::ExecCommand
+            }
+        );
+    }
+}
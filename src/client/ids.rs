@@ -0,0 +1,92 @@
+use rand::Rng;
+#[cfg(any(test, feature = "testing"))]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Produces the ids of the packets a connection sends, set with [`Builder::id_source`](super::Builder::id_source).
+/// Ids are masked to be non-negative since the server uses `-1` to signal a failed login.
+pub trait IdSource: Send + Sync {
+    /// The id for the next packet.
+    fn next_id(&self) -> i32;
+}
+
+/// Any id generating function, see [`Builder::id_generator`](super::Builder::id_generator).
+impl<F: Fn() -> i32 + Send + Sync> IdSource for F {
+    fn next_id(&self) -> i32 {
+        self()
+    }
+}
+
+/// Counts up from 1, the default, so ids can be followed in logs and matched up by eye.
+#[derive(Debug)]
+pub struct SequentialIdSource {
+    next: AtomicI32,
+}
+
+impl Default for SequentialIdSource {
+    fn default() -> Self {
+        SequentialIdSource {
+            next: AtomicI32::new(1),
+        }
+    }
+}
+
+impl IdSource for SequentialIdSource {
+    fn next_id(&self) -> i32 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Picks every id at random, so ids aren't reused by connections which reconnect.
+#[derive(Debug, Default)]
+pub struct RandomIdSource;
+
+impl IdSource for RandomIdSource {
+    fn next_id(&self) -> i32 {
+        rand::thread_rng().gen_range(0..=i32::MAX)
+    }
+}
+
+/// Hands out `ids` in order, starting over once they run out, for tests which check exact packets.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug)]
+pub struct FixedIdSource {
+    ids: Vec<i32>,
+    next: AtomicUsize,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FixedIdSource {
+    /// Creates a source handing out `ids`, which must not be empty.
+    pub fn new<I: IntoIterator<Item = i32>>(ids: I) -> Self {
+        let ids: Vec<_> = ids.into_iter().collect();
+        assert!(!ids.is_empty(), "a FixedIdSource needs at least one id");
+        FixedIdSource {
+            ids,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl IdSource for FixedIdSource {
+    fn next_id(&self) -> i32 {
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        self.ids[i % self.ids.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_hand_out_ids_in_order() {
+        let seq = SequentialIdSource::default();
+        assert_eq!([seq.next_id(), seq.next_id()], [1, 2]);
+        let fixed = FixedIdSource::new([7, 9]);
+        let ids: Vec<_> = (0..3).map(|_| fixed.next_id()).collect();
+        assert_eq!(ids, [7, 9, 7]);
+        assert!((0..100).all(|_| RandomIdSource.next_id() >= 0));
+    }
+}
@@ -0,0 +1,296 @@
+use super::{Connection, Error, Result};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace, warn};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::sleep,
+};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace, warn};
+
+/// Commands waiting on the supervisor before callers are made to wait to send more.
+const QUEUE_SIZE: usize = 64;
+
+/// The state of a [`PersistentConnection`]'s underlying connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Logged in and running commands
+    Connected,
+    /// The connection was lost, or never opened, and is being reopened
+    Reconnecting,
+    /// Every [`PersistentConnection`] was dropped and the supervisor stopped
+    Closed,
+    /// Reconnecting failed in a way retrying can't fix, like a rejected password, so the supervisor
+    /// stopped and commands fail with that error
+    Failed,
+}
+
+struct Request {
+    cmd: String,
+    reply: oneshot::Sender<Result<String>>,
+}
+
+/// A clonable connection which is kept logged in by a background task, reconnecting with the
+/// connection's backoff settings whenever it is lost. Commands are queued while it reconnects and
+/// any command cut off by the connection dropping is sent again afterwards, so commands should be
+/// safe to repeat. Created with [`Connection::persistent`].
+#[derive(Clone)]
+pub struct PersistentConnection {
+    requests: mpsc::Sender<Request>,
+    state: watch::Receiver<ConnectionState>,
+    /// Set by the supervisor before it stops on an error retrying can't fix
+    failure: Arc<OnceLock<Permanent>>,
+}
+
+impl PersistentConnection {
+    pub(super) fn new(conn: Connection) -> PersistentConnection {
+        let initial = if conn.is_healthy() {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Reconnecting
+        };
+        let (requests, rx) = mpsc::channel(QUEUE_SIZE);
+        let (state_tx, state) = watch::channel(initial);
+        let failure = Arc::new(OnceLock::new());
+        tokio::spawn(supervise(conn, rx, state_tx, Arc::clone(&failure)));
+        PersistentConnection {
+            requests,
+            state,
+            failure,
+        }
+    }
+
+    /// Queues a command and waits for its response, however many reconnects that takes.
+    /// Errors from the server, such as a rejected command, are returned as they are. Once reconnecting
+    /// fails in a way retrying can't fix, like `Error::AuthFailed`, every command fails with that error.
+    pub async fn cmd<C: ToString>(&self, cmd: C) -> Result<String> {
+        let (reply, response) = oneshot::channel();
+        let request = Request {
+            cmd: cmd.to_string(),
+            reply,
+        };
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| self.stopped())?;
+        response.await.map_err(|_| self.stopped())?
+    }
+
+    /// The error for a command the supervisor won't run.
+    fn stopped(&self) -> Error {
+        self.failure
+            .get()
+            .map_or(Error::Disconnected, Permanent::to_error)
+    }
+
+    /// The current state of the underlying connection.
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// A receiver which is notified each time the state of the underlying connection changes.
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+}
+
+/// An error reconnecting again can't fix, like a rejected password or a bad setting.
+/// Unlike [`Error`] it can be cloned, so every waiting command can be failed with it.
+#[derive(Debug, Clone)]
+pub(super) enum Permanent {
+    AuthFailed,
+    BlankPassword,
+    EmptyPassword,
+    InvalidAuthId,
+    MissingEnv(String),
+}
+
+impl Permanent {
+    /// Whether `e` is permanent, and which error it is if so.
+    pub(super) fn from_error(e: &Error) -> Option<Permanent> {
+        match e {
+            Error::AuthFailed => Some(Permanent::AuthFailed),
+            Error::BlankPassword => Some(Permanent::BlankPassword),
+            Error::EmptyPassword => Some(Permanent::EmptyPassword),
+            Error::InvalidAuthId => Some(Permanent::InvalidAuthId),
+            Error::MissingEnv(name) => Some(Permanent::MissingEnv(name.clone())),
+            _ => None,
+        }
+    }
+
+    fn to_error(&self) -> Error {
+        match self {
+            Permanent::AuthFailed => Error::AuthFailed,
+            Permanent::BlankPassword => Error::BlankPassword,
+            Permanent::EmptyPassword => Error::EmptyPassword,
+            Permanent::InvalidAuthId => Error::InvalidAuthId,
+            Permanent::MissingEnv(name) => Error::MissingEnv(name.clone()),
+        }
+    }
+}
+
+/// Runs queued commands, reopening the connection whenever it is lost, until every sender is dropped
+/// or reconnecting fails permanently.
+async fn supervise(
+    mut conn: Connection,
+    mut requests: mpsc::Receiver<Request>,
+    state: watch::Sender<ConnectionState>,
+    failure: Arc<OnceLock<Permanent>>,
+) {
+    let mut replay: VecDeque<Request> = VecDeque::new();
+    loop {
+        if !conn.is_healthy() {
+            state.send_replace(ConnectionState::Reconnecting);
+            let mut attempt = 0;
+            while let Err(e) = conn.reconnect().await {
+                if let Some(fatal) = Permanent::from_error(&e) {
+                    warn!("giving up reconnecting to {}: {}", conn.host, e);
+                    // set before closing the queue, so callers which can't send see it
+                    let fatal = failure.get_or_init(|| fatal);
+                    requests.close();
+                    for r in replay.drain(..) {
+                        let _ = r.reply.send(Err(fatal.to_error()));
+                    }
+                    while let Some(r) = requests.recv().await {
+                        let _ = r.reply.send(Err(fatal.to_error()));
+                    }
+                    state.send_replace(ConnectionState::Failed);
+                    return;
+                }
+                if requests.is_closed() && replay.is_empty() {
+                    break;
+                }
+                attempt += 1;
+                let delay = conn.backoff_delay(attempt);
+                warn!(
+                    "failed to reconnect to {}, retrying in {:?}: {}",
+                    conn.host, delay, e
+                );
+                sleep(delay).await;
+            }
+            if conn.is_healthy() {
+                debug!("reconnected to {}", conn.host);
+                state.send_replace(ConnectionState::Connected);
+            }
+        }
+        let request = match replay.pop_front() {
+            Some(r) => r,
+            None => match requests.recv().await {
+                Some(r) => r,
+                None => break,
+            },
+        };
+        if request.reply.is_closed() {
+            trace!("dropping command \"{}\" nobody is waiting on", request.cmd);
+            continue;
+        }
+        match conn.cmd(&request.cmd).await {
+            Err(e) if !conn.is_healthy() => {
+                debug!(
+                    "connection lost running \"{}\", replaying it: {}",
+                    request.cmd, e
+                );
+                replay.push_front(request);
+            }
+            res => {
+                let _ = request.reply.send(res);
+            }
+        }
+    }
+    trace!("stopping the persistent connection to {}", conn.host);
+    state.send_replace(ConnectionState::Closed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{accept, accept_auth, echo};
+    use super::*;
+    use crate::packet::{Packet, PacketType};
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn commands_succeed_after_the_server_restarts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            // the server goes down, dropping the connection, and comes back up on the same port
+            drop(s);
+            drop(listener);
+            sleep(Duration::from_millis(50)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let conn = Connection::builder()
+            .max_retries(1)
+            .retry_delay(Duration::from_millis(10))
+            .connect(addr, "password")
+            .await
+            .unwrap()
+            .persistent();
+        let mut states = conn.subscribe();
+        assert_eq!(conn.state(), ConnectionState::Connected);
+        assert_eq!(conn.cmd("list").await.unwrap(), "echo: list");
+
+        let res = tokio::time::timeout(Duration::from_secs(5), conn.cmd("time"));
+        assert_eq!(res.await.unwrap().unwrap(), "echo: time");
+        assert_eq!(conn.cmd("again").await.unwrap(), "echo: again");
+        assert_eq!(conn.state(), ConnectionState::Connected);
+
+        drop(conn);
+        states
+            .wait_for(|s| *s == ConnectionState::Closed)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_passwords_fail_commands_instead_of_retrying() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            accept_auth(&mut s).await;
+            drop(s);
+            // the password was changed while the connection was down
+            loop {
+                let mut s = accept(&listener).await;
+                s.next().await.unwrap().unwrap();
+                s.send(Packet {
+                    ptype: PacketType::AuthResponse,
+                    id: -1,
+                    body: String::new(),
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        let conn = Connection::builder()
+            .retry_delay(Duration::from_millis(10))
+            .connect(addr, "password")
+            .await
+            .unwrap()
+            .persistent();
+        let mut states = conn.subscribe();
+        let res = tokio::time::timeout(Duration::from_secs(5), conn.cmd("list"));
+        assert!(matches!(res.await.unwrap(), Err(Error::AuthFailed)));
+        states
+            .wait_for(|s| *s == ConnectionState::Failed)
+            .await
+            .unwrap();
+        assert!(matches!(conn.cmd("list").await, Err(Error::AuthFailed)));
+    }
+}
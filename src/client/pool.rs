@@ -0,0 +1,244 @@
+use super::{resolve, system_resolver, Builder, Connection, Result};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace};
+
+/// A clonable pool of up to `max_size` logged in connections to one server, for running many commands at once.
+/// Connections are opened as they are needed and reused once they are returned.
+/// ```no_run
+/// # async fn f() -> Result<(), rcon_rs::client::Error> {
+/// use rcon_rs::client::ConnectionPool;
+/// let pool = ConnectionPool::builder("localhost:25575", "password").max_size(4).build();
+/// let mut c = pool.acquire().await?;
+/// println!("{}", c.cmd("list").await?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConnectionPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    host: String,
+    password: String,
+    max_size: usize,
+    /// Limits the connections in use or idle to `max_size`
+    slots: Arc<Semaphore>,
+    idle: StdMutex<Vec<Connection>>,
+    template: Mutex<Template>,
+}
+
+/// What new connections are made from, the builder until the host is first resolved.
+struct Template {
+    builder: Option<Builder>,
+    config: Option<Connection>,
+}
+
+/// A builder for a [`ConnectionPool`].
+pub struct ConnectionPoolBuilder {
+    host: String,
+    password: String,
+    max_size: usize,
+    builder: Builder,
+}
+
+impl ConnectionPoolBuilder {
+    /// Sets the most connections the pool opens, defaults to 4.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the settings every connection in the pool is made with.
+    pub fn connection_builder(mut self, builder: Builder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    /// Creates the pool, which doesn't connect until a connection is first acquired.
+    pub fn build(self) -> ConnectionPool {
+        ConnectionPool {
+            shared: Arc::new(Shared {
+                host: self.host,
+                password: self.password,
+                max_size: self.max_size,
+                slots: Arc::new(Semaphore::new(self.max_size)),
+                idle: StdMutex::new(Vec::new()),
+                template: Mutex::new(Template {
+                    builder: Some(self.builder),
+                    config: None,
+                }),
+            }),
+        }
+    }
+}
+
+impl ConnectionPool {
+    /// Creates a builder for a pool of connections to `host` logged in with `password`.
+    pub fn builder<S: ToString, P: ToString>(host: S, password: P) -> ConnectionPoolBuilder {
+        ConnectionPoolBuilder {
+            host: host.to_string(),
+            password: password.to_string(),
+            max_size: 4,
+            builder: Connection::builder(),
+        }
+    }
+
+    /// The most connections the pool opens.
+    pub fn max_size(&self) -> usize {
+        self.shared.max_size
+    }
+
+    /// Takes an idle connection, or opens a new one if there aren't any, waiting while `max_size` are in use.
+    /// Idle connections which were dropped are replaced. The connection goes back to the pool when dropped.
+    pub async fn acquire(&self) -> Result<PooledConnection> {
+        let permit = Arc::clone(&self.shared.slots)
+            .acquire_owned()
+            .await
+            .expect("the pool's semaphore is never closed");
+        let idle = self.shared.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(c) if c.is_healthy() => c,
+            Some(_) => {
+                debug!("replacing a pooled connection which was dropped");
+                self.shared.open().await?
+            }
+            None => self.shared.open().await?,
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            shared: Arc::clone(&self.shared),
+            _permit: permit,
+        })
+    }
+}
+
+impl Shared {
+    /// Opens and logs in a new connection.
+    async fn open(&self) -> Result<Connection> {
+        let mut template = self.template.lock().await;
+        if template.config.is_none() {
+            let builder = template.builder.as_ref().expect("taken once resolved");
+            let resolver = builder.resolver.clone().unwrap_or_else(system_resolver);
+            let host = resolve(&resolver, self.host.clone()).await?;
+            builder.validate(&self.password)?;
+            // only taken once building can't fail, so a failed acquire leaves it for the next one
+            let builder = template.builder.take().unwrap();
+            template.config = Some(builder.build(host, &self.password)?);
+        }
+        let mut c = template.config.as_ref().unwrap().clone_config();
+        drop(template);
+        trace!("opening a pooled connection to {}", c.host);
+        c.open().await?;
+        Ok(c)
+    }
+}
+
+/// A connection taken from a [`ConnectionPool`], which goes back to the pool when dropped.
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    shared: Arc<Shared>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    /// Returns the connection to the pool, unless it was dropped and has to be replaced.
+    fn drop(&mut self) {
+        if let Some(c) = self.conn.take() {
+            if c.is_healthy() {
+                self.shared.idle.lock().unwrap().push(c);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{accept, accept_auth};
+    use super::*;
+    use crate::packet::{Packet, PacketType};
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn runs_concurrent_commands_on_at_most_max_size_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&opened);
+        tokio::spawn(async move {
+            loop {
+                let mut s = accept(&listener).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    accept_auth(&mut s).await;
+                    while let Some(Ok(p)) = s.next().await {
+                        let body = format!("echo: {}", p.body);
+                        s.send(Packet {
+                            ptype: PacketType::ResponseValue,
+                            id: p.id,
+                            body,
+                        })
+                        .await
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        let pool = ConnectionPool::builder(addr, "password")
+            .max_size(3)
+            .build();
+        let tasks: Vec<_> = (0..12)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let mut c = pool.acquire().await.unwrap();
+                    let res = c.cmd(format!("cmd{}", i)).await.unwrap();
+                    tokio::task::yield_now().await;
+                    (i, res)
+                })
+            })
+            .collect();
+        for t in tasks {
+            let (i, res) = t.await.unwrap();
+            assert_eq!(res, format!("echo: cmd{}", i));
+        }
+        let opened = opened.load(Ordering::SeqCst);
+        assert!((1..=3).contains(&opened), "{} connections", opened);
+        assert_eq!(pool.max_size(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_builds_keep_the_builder() {
+        let pool = ConnectionPool::builder("127.0.0.1:25575", "").build();
+        for _ in 0..2 {
+            assert!(matches!(
+                pool.acquire().await,
+                Err(crate::client::Error::EmptyPassword)
+            ));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Text which servers put in the body of a failed command, checked by [`Response::is_error_hint`] by default.
+pub const DEFAULT_ERROR_HINTS: &[&str] = &[
+    "Unknown command",
+    "Unknown or incomplete command",
+    "Incorrect argument for command",
+];
+
+/// The body of a response, with a check for error text, returned by [`Connection::cmd_response`](super::Connection::cmd_response).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    body: String,
+    error_hint: bool,
+}
+
+impl Response {
+    /// Wraps `body`, which looks like an error if any of `hints` appears in it.
+    pub fn new<S: Into<String>, H: AsRef<str>>(body: S, hints: &[H]) -> Response {
+        let body = body.into();
+        let error_hint = hints.iter().any(|h| body.contains(h.as_ref()));
+        Response { body, error_hint }
+    }
+
+    /// Whether the body contains text the server uses to report a failed command.
+    /// It is only a hint, since a successful command can print anything.
+    pub fn is_error_hint(&self) -> bool {
+        self.error_hint
+    }
+
+    /// The body.
+    pub fn as_str(&self) -> &str {
+        &self.body
+    }
+
+    /// Takes the body.
+    pub fn into_string(self) -> String {
+        self.body
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.body)
+    }
+}
+
+impl AsRef<str> for Response {
+    fn as_ref(&self) -> &str {
+        &self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_bodies_by_hints() {
+        let normal = Response::new(
+            "There are 0 of a max of 20 players online",
+            DEFAULT_ERROR_HINTS,
+        );
+        assert!(!normal.is_error_hint());
+        assert_eq!(normal.as_str(), "There are 0 of a max of 20 players online");
+
+        let error = Response::new(
+            "Unknown or incomplete command, see below for error",
+            DEFAULT_ERROR_HINTS,
+        );
+        assert!(error.is_error_hint());
+        assert!(Response::new("nope", &["nope"]).is_error_hint());
+        assert_eq!(
+            error.into_string(),
+            "Unknown or incomplete command, see below for error"
+        );
+    }
+}
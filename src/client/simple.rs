@@ -0,0 +1,148 @@
+use super::{resolve, system_resolver, Builder, Connection, Error, Result};
+
+/// A lower level client which connects and logs in as separate steps, reporting a rejected password as `false`
+/// instead of an error.
+/// ```no_run
+/// # async fn f() -> Result<(), rcon_rs::client::Error> {
+/// let mut client = rcon_rs::Client::new("localhost:25575");
+/// if client.login("password").await? {
+///     println!("{}", client.run("list").await?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Client {
+    addr: String,
+    builder: Option<Builder>,
+    conn: Option<Connection>,
+}
+
+impl Client {
+    /// Creates a client for the server at `addr`, which isn't connected until `login` is called.
+    pub fn new<A: ToString>(addr: A) -> Client {
+        Self::with_builder(addr, Connection::builder().allow_empty_password(true))
+    }
+
+    /// Creates a client which connects with the options set on `builder`.
+    pub fn with_builder<A: ToString>(addr: A, builder: Builder) -> Client {
+        Client {
+            addr: addr.to_string(),
+            builder: Some(builder),
+            conn: None,
+        }
+    }
+
+    /// Connects if needed and logs in, returning whether the password was accepted.
+    /// Logging in again, for example with a different password, reuses the connection.
+    pub async fn login<P: ToString>(&mut self, password: P) -> Result<bool> {
+        let conn = match &mut self.conn {
+            Some(c) => {
+                c.password = password.to_string();
+                c
+            }
+            None => {
+                let password = password.to_string();
+                let builder = self.builder.as_ref().expect("only taken once built");
+                let resolver = builder.resolver.clone().unwrap_or_else(system_resolver);
+                let host = resolve(&resolver, self.addr.clone()).await?;
+                builder.validate(&password)?;
+                // kept until building can't fail, then the connection keeps its settings across failed logins
+                let builder = self.builder.take().unwrap();
+                self.conn.insert(builder.build(host, password)?)
+            }
+        };
+        conn.try_login().await
+    }
+
+    /// Runs a command, failing with `Error::Unauthenticated` unless `login` has succeeded.
+    pub async fn run<C: ToString>(&mut self, cmd: C) -> Result<String> {
+        match &mut self.conn {
+            Some(c) if c.authenticated => c.cmd(cmd).await,
+            _ => Err(Error::Unauthenticated),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{accept, accept_auth, echo};
+    use super::*;
+    use crate::packet::{Packet, PacketType};
+    use futures::{SinkExt, StreamExt};
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn login_then_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let p = s.next().await.unwrap().unwrap();
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: -1,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            assert_eq!(p.body, "wrong");
+            accept_auth(&mut s).await;
+            echo(&mut s).await;
+        });
+
+        let mut client = Client::new(addr);
+        assert!(matches!(
+            client.run("list").await,
+            Err(Error::Unauthenticated)
+        ));
+        assert!(!client.login("wrong").await.unwrap());
+        assert!(matches!(
+            client.run("list").await,
+            Err(Error::Unauthenticated)
+        ));
+        assert!(client.login("password").await.unwrap());
+        assert_eq!(client.run("list").await.unwrap(), "echo: list");
+    }
+
+    #[tokio::test]
+    async fn failed_resolution_keeps_the_builder() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut s = accept(&listener).await;
+            let p = s.next().await.unwrap().unwrap();
+            assert_eq!(p.id, 42);
+            s.send(Packet {
+                ptype: PacketType::AuthResponse,
+                id: p.id,
+                body: String::new(),
+            })
+            .await
+            .unwrap();
+            echo(&mut s).await;
+        });
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let builder = Connection::builder().auth_id(Some(42)).resolver(move |_| {
+            let first = lookups.fetch_add(1, Ordering::SeqCst) == 0;
+            async move {
+                if first {
+                    Err(io::Error::new(io::ErrorKind::NotFound, "no such host"))
+                } else {
+                    Ok(vec![addr])
+                }
+            }
+        });
+        let mut client = Client::with_builder("rcon.example:25575", builder);
+        assert!(matches!(client.login("password").await, Err(Error::Io(_))));
+        assert!(client.login("password").await.unwrap());
+        assert_eq!(client.run("list").await.unwrap(), "echo: list");
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// The longest packet the RCON spec allows, which a rendered command has to fit in.
+const MAX_PACKET_LENGTH: usize = 4096;
+
+/// Why a [`Command`] can't be sent as it is.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CommandError {
+    /// The packet holding the command would be longer than the maximum packet length, contains its length.
+    TooLong(usize),
+    /// The command contains a null byte, which servers read as the end of the body, contains its position.
+    NulByte(usize),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            CommandError::TooLong(len) => {
+                write!(
+                    f,
+                    "the command's packet is {} bytes, longer than the maximum",
+                    len
+                )
+            }
+            CommandError::NulByte(pos) => {
+                write!(f, "the command contains a null byte at {}", pos)
+            }
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+/// How arguments are quoted when a [`Command`] is rendered.
+///
+/// Arguments which are empty or contain whitespace, quotes or other special characters are wrapped in
+/// double quotes. Newlines and carriage returns are replaced with spaces in every part of the
+/// command, since servers treat them as the end of a command.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Escaping {
+    /// Minecraft's quoted strings, where `"` and `\` are escaped with a backslash.
+    #[default]
+    Minecraft,
+    /// The Source engine's tokenizer, which has no escapes, so `"` is replaced with `'`.
+    /// Arguments containing `;` are quoted so they can't start a new command.
+    Source,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Arg {
+    Quoted(String),
+    Raw(String),
+}
+
+/// A command built from a name and arguments, escaped when it is rendered for a server.
+/// ```
+/// use rcon_rs::command::{Command, Escaping};
+/// let cmd = Command::new("say").arg("hello world").arg("");
+/// assert_eq!(cmd.render(Escaping::Minecraft), r#"say "hello world" """#);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Command {
+    name: String,
+    args: Vec<Arg>,
+}
+
+impl Command {
+    /// Creates a command with no arguments.
+    pub fn new<N: ToString>(name: N) -> Self {
+        Command {
+            name: name.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds an argument, which is quoted if needed.
+    pub fn arg<A: ToString>(mut self, arg: A) -> Self {
+        self.args.push(Arg::Quoted(arg.to_string()));
+        self
+    }
+
+    /// Adds an argument which is sent as it is, for arguments like JSON text components which must not be quoted.
+    /// Newlines are still replaced.
+    pub fn raw_arg<A: ToString>(mut self, arg: A) -> Self {
+        self.args.push(Arg::Raw(arg.to_string()));
+        self
+    }
+
+    /// Joins the name and arguments with spaces, quoting arguments as `escaping` requires.
+    pub fn render(&self, escaping: Escaping) -> String {
+        let mut out = single_line(&self.name);
+        for arg in &self.args {
+            out.push(' ');
+            match arg {
+                Arg::Raw(a) => out.push_str(&single_line(a)),
+                Arg::Quoted(a) => out.push_str(&quote(&single_line(a), escaping)),
+            }
+        }
+        out
+    }
+
+    /// Checks that the command can be sent as it is: it must have no null bytes, and fit in a 4096 byte
+    /// packet when rendered with the default escaping.
+    pub fn validate(&self) -> Result<(), CommandError> {
+        check(&self.render(Escaping::default()), MAX_PACKET_LENGTH)
+    }
+}
+
+/// Checks a rendered command against a connection's `max_length`.
+pub(crate) fn check(rendered: &str, max_length: usize) -> Result<(), CommandError> {
+    if let Some(pos) = rendered.find('\0') {
+        return Err(CommandError::NulByte(pos));
+    }
+    // the id, type and two null terminators
+    let len = rendered.len() + 10;
+    if len > max_length {
+        return Err(CommandError::TooLong(len));
+    }
+    Ok(())
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\n', '\r'], " ")
+}
+
+fn quote(arg: &str, escaping: Escaping) -> String {
+    let special = |c: char| {
+        c.is_whitespace()
+            || c == '"'
+            || match escaping {
+                Escaping::Minecraft => c == '\\' || c == '\'',
+                Escaping::Source => c == ';',
+            }
+    };
+    if !arg.is_empty() && !arg.contains(special) {
+        return arg.to_owned();
+    }
+    let inner = match escaping {
+        Escaping::Minecraft => arg.replace('\\', "\\\\").replace('"', "\\\""),
+        Escaping::Source => arg.replace('"', "'"),
+    };
+    format!("\"{}\"", inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_args_are_joined() {
+        let cmd = Command::new("give").arg("@p").arg("diamond").arg(64);
+        assert_eq!(cmd.render(Escaping::Minecraft), "give @p diamond 64");
+        assert_eq!(cmd.render(Escaping::Source), "give @p diamond 64");
+    }
+
+    #[test]
+    fn args_with_spaces_and_empty_args_are_quoted() {
+        let cmd = Command::new("say").arg("hello world").arg("");
+        assert_eq!(cmd.render(Escaping::Minecraft), r#"say "hello world" """#);
+        assert_eq!(cmd.render(Escaping::Source), r#"say "hello world" """#);
+    }
+
+    #[test]
+    fn quotes_are_escaped() {
+        let cmd = Command::new("say").arg(r#"a "b" \c"#);
+        assert_eq!(cmd.render(Escaping::Minecraft), r#"say "a \"b\" \\c""#);
+        assert_eq!(cmd.render(Escaping::Source), r#"say "a 'b' \c""#);
+    }
+
+    #[test]
+    fn source_quotes_command_separators() {
+        let cmd = Command::new("say").arg("hi;quit");
+        assert_eq!(cmd.render(Escaping::Source), r#"say "hi;quit""#);
+        assert_eq!(cmd.render(Escaping::Minecraft), "say hi;quit");
+    }
+
+    #[test]
+    fn newlines_are_replaced() {
+        let cmd = Command::new("say").arg("hi\nstop").raw_arg("{}\r\nstop");
+        assert_eq!(cmd.render(Escaping::Minecraft), r#"say "hi stop" {}  stop"#);
+    }
+
+    #[test]
+    fn raw_args_are_not_quoted() {
+        let cmd = Command::new("tellraw")
+            .arg("@a")
+            .raw_arg(r#"{"text":"hi there"}"#);
+        assert_eq!(
+            cmd.render(Escaping::Minecraft),
+            r#"tellraw @a {"text":"hi there"}"#
+        );
+    }
+
+    #[test]
+    fn validate_rejects_over_length_commands() {
+        let cmd = Command::new("say").arg("a".repeat(4082));
+        assert_eq!(cmd.validate(), Ok(()));
+        let cmd = Command::new("say").arg("a".repeat(4083));
+        assert_eq!(cmd.validate(), Err(CommandError::TooLong(4097)));
+    }
+
+    #[test]
+    fn validate_rejects_null_bytes() {
+        let cmd = Command::new("say").arg("hi\0stop");
+        assert_eq!(cmd.validate(), Err(CommandError::NulByte(6)));
+        let cmd = Command::new("say").raw_arg("\0");
+        assert_eq!(cmd.validate(), Err(CommandError::NulByte(4)));
+    }
+}
@@ -0,0 +1,167 @@
+use super::client::{Connection, Error};
+use super::minecraft::parse_list;
+use super::profile::InfoQuery;
+
+/// What a server reports about itself, fields the server doesn't report are `None`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ServerInfo {
+    /// The server's name, as shown in the server browser.
+    pub name: Option<String>,
+    /// The game or server software version.
+    pub version: Option<String>,
+    /// The current map.
+    pub map: Option<String>,
+    /// The number of players online.
+    pub players: Option<u32>,
+    /// The maximum number of players.
+    pub max_players: Option<u32>,
+}
+
+impl ServerInfo {
+    /// Builds the info from the responses to Minecraft's `list` and `version` commands.
+    /// Vanilla servers don't have `version`, so it is only filled in for server software which does.
+    pub fn from_minecraft(list: &str, version: &str) -> ServerInfo {
+        let (players, max_players) = match parse_list(list) {
+            Ok(l) => (Some(l.current), Some(l.max)),
+            Err(_) => (None, None),
+        };
+        ServerInfo {
+            version: minecraft_version(version),
+            players,
+            max_players,
+            ..ServerInfo::default()
+        }
+    }
+
+    /// Builds the info from the response to the Source engine's `status` command.
+    pub fn from_source_status(status: &str) -> ServerInfo {
+        let mut info = ServerInfo::default();
+        for line in status.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "hostname" => info.name = Some(value.to_owned()),
+                "version" => info.version = value.split_whitespace().next().map(str::to_owned),
+                "map" => info.map = value.split_whitespace().next().map(str::to_owned),
+                // `3 humans, 0 bots (20/0 max)` or `3 (20 max)` on older games
+                "players" => {
+                    info.players = leading_number(value);
+                    info.max_players = value.split_once('(').and_then(|(_, m)| leading_number(m));
+                }
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+fn leading_number(s: &str) -> Option<u32> {
+    let digits: String = s
+        .trim_start()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// Gets the version from a `version` response, preferring the Minecraft version if the server software reports its own too.
+fn minecraft_version(response: &str) -> Option<String> {
+    let response = response.trim();
+    if response.is_empty() || response.starts_with("Unknown") {
+        return None;
+    }
+    if let Some((_, rest)) = response.split_once("(MC: ") {
+        if let Some((v, _)) = rest.split_once(')') {
+            return Some(v.trim().to_owned());
+        }
+    }
+    response.lines().next().map(|l| l.trim().to_owned())
+}
+
+impl Connection {
+    /// Runs the commands the profile uses to describe the server and collects what they report.
+    /// Commands the server doesn't support leave their fields as `None`, the generic profile doesn't run any.
+    pub async fn server_info(&mut self) -> Result<ServerInfo, Error> {
+        match self.profile().info_query() {
+            InfoQuery::None => Ok(ServerInfo::default()),
+            InfoQuery::Minecraft => {
+                let list = self.cmd("list").await?;
+                let version = self.cmd("version").await?;
+                Ok(ServerInfo::from_minecraft(&list, &version))
+            }
+            InfoQuery::Source => {
+                let status = self.cmd("status").await?;
+                Ok(ServerInfo::from_source_status(&status))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_source_status() {
+        let status = "hostname: My Server\n\
+            version : 1.38.2.2/13822 1423/8285 secure  [G:1:123456]\n\
+            udp/ip  : 0.0.0.0:27015  (public ip: 203.0.113.7)\n\
+            os      :  Linux\n\
+            type    :  community dedicated\n\
+            map     : de_dust2\n\
+            players : 3 humans, 0 bots (20/0 max) (not hibernating)\n\
+            \n\
+            # userid name uniqueid connected ping loss state rate adr\n";
+        assert_eq!(
+            ServerInfo::from_source_status(status),
+            ServerInfo {
+                name: Some("My Server".into()),
+                version: Some("1.38.2.2/13822".into()),
+                map: Some("de_dust2".into()),
+                players: Some(3),
+                max_players: Some(20),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_old_source_status() {
+        let info = ServerInfo::from_source_status(
+            "hostname: TF2 Server\nmap     : ctf_2fort at: 0 x, 0 y, 0 z\nplayers : 5 (24 max)\n",
+        );
+        assert_eq!(info.map.as_deref(), Some("ctf_2fort"));
+        assert_eq!((info.players, info.max_players), (Some(5), Some(24)));
+        assert_eq!(info.version, None);
+    }
+
+    #[test]
+    fn parses_minecraft_responses() {
+        let info = ServerInfo::from_minecraft(
+            "There are 2 of a max of 20 players online: Alice, Bob",
+            "This server is running Paper version git-Paper-196 (MC: 1.19.2) (Implementing API version 1.19.2-R0.1-SNAPSHOT)",
+        );
+        assert_eq!(
+            info,
+            ServerInfo {
+                version: Some("1.19.2".into()),
+                players: Some(2),
+                max_players: Some(20),
+                ..ServerInfo::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unsupported_minecraft_commands_are_none() {
+        let info = ServerInfo::from_minecraft(
+            "Unknown or incomplete command, see below for error",
+            "Unknown or incomplete command, see below for error",
+        );
+        assert_eq!(info, ServerInfo::default());
+    }
+}
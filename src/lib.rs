@@ -14,14 +14,49 @@
 //!     Ok(())
 //! }
 //! ```
+//! Logging goes through the [`log`](https://docs.rs/log) facade and the library never installs a logger,
+//! applications choose their own or enable the `cli` feature for [`init_logger`](cli::init_logger).
 
 /// A [RCON](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol) connection for interacting with remote servers.
 #[cfg(feature = "client")]
 pub mod client;
-pub(crate) mod packet;
+/// The packet format and a codec for framing it.
+pub mod packet;
+/// Packet framing on plain byte buffers, for event loops other than tokio.
+pub mod sans_io;
+/// The byte streams connections and sessions can run over.
+#[cfg(feature = "runtime")]
+pub mod transport;
+/// Helpers for cleaning up response text.
+pub mod util;
 #[cfg(feature = "client")]
-pub use client::Connection;
+pub use client::{Client, Connection};
+/// The BattlEye RCon protocol, selected with [`Builder::protocol`](client::Builder::protocol).
+#[cfg(feature = "client")]
+pub mod battleye;
+/// A blocking client for code that doesn't run inside an async runtime.
+#[cfg(feature = "blocking")]
+pub mod blocking;
+/// A builder for commands with escaped arguments.
+#[cfg(feature = "client")]
+pub mod command;
+/// Querying a server's name, version and player counts.
+#[cfg(feature = "client")]
+pub mod info;
+/// Helpers for parsing the output of Minecraft server commands.
+#[cfg(feature = "client")]
+pub mod minecraft;
+/// Profiles for adapting commands to different kinds of servers.
+#[cfg(feature = "client")]
+pub mod profile;
+/// A rcon server which delegates authentication and commands to a [`RconImpl`](server::RconImpl).
 #[cfg(feature = "server")]
 pub mod server;
 #[cfg(feature = "server")]
 pub use server::*;
+/// Setup for binaries built on the crate.
+#[cfg(feature = "cli")]
+pub mod cli;
+/// A scripted server for testing code which uses the client.
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -0,0 +1,132 @@
+use super::client::{Connection, Error};
+use super::util::strip_minecraft_formatting;
+
+/// The parsed output of the `list` command.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ListResult {
+    /// The number of players online.
+    pub current: u32,
+    /// The maximum number of players.
+    pub max: u32,
+    /// The names of the players online.
+    pub players: Vec<String>,
+}
+
+/// Parses the output of `list`, handling the formats used by different versions and server software:
+/// - `There are 2 of a max of 20 players online: Alice, Bob` (1.16+)
+/// - `There are 2 of a max 20 players online: Alice, Bob` (1.13 - 1.15)
+/// - `There are 2/20 players online:Alice, Bob` (1.12 and older, names may be on the next line)
+/// - `There are 2 out of maximum 20 players online.` (Bukkit based servers, names may follow)
+pub fn parse_list(response: &str) -> Result<ListResult, Error> {
+    let (head, names) = match response.find(':') {
+        Some(i) => (&response[..i], &response[i + 1..]),
+        None => (response, ""),
+    };
+    if !head.contains("players online") {
+        return Err(Error::InvalidResponse);
+    }
+
+    let mut counts = head
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .map(str::parse::<u32>);
+    let (current, max) = match (counts.next(), counts.next()) {
+        (Some(Ok(current)), Some(Ok(max))) => (current, max),
+        _ => return Err(Error::InvalidResponse),
+    };
+
+    let players = names
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect();
+    Ok(ListResult {
+        current,
+        max,
+        players,
+    })
+}
+
+impl Connection {
+    /// Runs `list` and parses the player counts and names.
+    pub async fn list(&mut self) -> Result<ListResult, Error> {
+        let response = self.cmd("list").await?;
+        parse_list(&response)
+    }
+
+    /// Runs a command and removes the `§` formatting codes from the response, see [`strip_minecraft_formatting`].
+    pub async fn cmd_stripped<C: ToString>(&mut self, cmd: C) -> Result<String, Error> {
+        let response = self.cmd(cmd).await?;
+        Ok(strip_minecraft_formatting(&response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(current: u32, max: u32, players: &[&str]) -> ListResult {
+        ListResult {
+            current,
+            max,
+            players: players.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_modern_format() {
+        assert_eq!(
+            parse_list("There are 2 of a max of 20 players online: Alice, Bob").unwrap(),
+            list(2, 20, &["Alice", "Bob"])
+        );
+        assert_eq!(
+            parse_list("There are 1 of a max 10 players online: Alice").unwrap(),
+            list(1, 10, &["Alice"])
+        );
+    }
+
+    #[test]
+    fn parses_legacy_format() {
+        assert_eq!(
+            parse_list("There are 2/20 players online:Alice, Bob").unwrap(),
+            list(2, 20, &["Alice", "Bob"])
+        );
+        assert_eq!(
+            parse_list("There are 1/20 players online:\nAlice\n").unwrap(),
+            list(1, 20, &["Alice"])
+        );
+    }
+
+    #[test]
+    fn parses_bukkit_format() {
+        assert_eq!(
+            parse_list("There are 1 out of maximum 20 players online.").unwrap(),
+            list(1, 20, &[])
+        );
+    }
+
+    #[test]
+    fn parses_empty_server() {
+        assert_eq!(
+            parse_list("There are 0 of a max of 20 players online: ").unwrap(),
+            list(0, 20, &[])
+        );
+        assert_eq!(
+            parse_list("There are 0/20 players online:").unwrap(),
+            list(0, 20, &[])
+        );
+    }
+
+    #[test]
+    fn rejects_other_output() {
+        assert!(matches!(
+            parse_list("Unknown command"),
+            Err(Error::InvalidResponse)
+        ));
+        assert!(matches!(
+            parse_list("There are many players online"),
+            Err(Error::InvalidResponse)
+        ));
+    }
+}
@@ -1,28 +1,49 @@
+#[cfg(feature = "runtime")]
+use super::sans_io::PacketFramer;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-//use log::*;
-use std::io::{self, Error as IoError, Read};
+#[cfg(feature = "runtime")]
+use std::io;
+use std::io::Error as IoError;
+#[cfg(feature = "runtime")]
 use tokio_util::codec::*;
 
 type Result<T> = std::result::Result<T, PacketError>;
 
+/// Prefix this crate's server puts in front of a `ResponseValue` body when a command fails.
+/// Rcon has no error channel, so the client only decodes it when talking to a compatible server.
+pub const ERROR_PREFIX: &str = "\x01ERR\x01";
+
+/// The type of a packet.
+/// Serialized by name since `AuthResponse` and `ExecCommand` share the same value on the wire.
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PacketType {
+    /// `SERVERDATA_AUTH`, sent by the client to log in.
     Auth,
+    /// `SERVERDATA_AUTH_RESPONSE`, sent by the server in reply to `Auth`.
     AuthResponse,
+    /// `SERVERDATA_EXECCOMMAND`, sent by the client to run a command.
     ExecCommand,
+    /// `SERVERDATA_RESPONSE_VALUE`, sent by the server in reply to `ExecCommand`.
     ResponseValue,
+    /// The wire value 2 decoded by a [`CodecType::Proxy`] codec. It is an `AuthResponse` when the server sent it
+    /// and an `ExecCommand` when the client did, which only the proxy knows, see [`PacketType::resolve`].
+    Ambiguous,
 }
 
 impl PacketType {
+    /// Gets the packet type from its wire value, which depends on which side is decoding it.
     pub fn from_i32(i: i32, codec: CodecType) -> Option<PacketType> {
         match i {
             0 => Some(PacketType::ResponseValue),
             2 if codec == CodecType::Client => Some(PacketType::AuthResponse),
             2 if codec == CodecType::Server => Some(PacketType::ExecCommand),
+            2 => Some(PacketType::Ambiguous),
             3 => Some(PacketType::Auth),
             _ => None,
         }
     }
+    /// The wire value of the packet type.
     pub fn bytes(&self) -> i32 {
         match self {
             PacketType::ResponseValue => 0,
@@ -30,9 +51,20 @@ impl PacketType {
             // part of the rcon spec :/
             PacketType::AuthResponse => 2,
             PacketType::ExecCommand => 2,
+            PacketType::Ambiguous => 2,
             PacketType::Auth => 3,
         }
     }
+
+    /// Resolves an `Ambiguous` type as if it was decoded by a `codec` of the side receiving it,
+    /// so `CodecType::Client` for packets from the server and `CodecType::Server` for packets from the client.
+    /// Other types are returned unchanged.
+    pub fn resolve(self, codec: CodecType) -> PacketType {
+        match self {
+            PacketType::Ambiguous => PacketType::from_i32(2, codec).unwrap_or(self),
+            t => t,
+        }
+    }
 }
 
 use std::{
@@ -40,10 +72,18 @@ use std::{
     fmt::{self, Display, Formatter},
 };
 
+/// Errors that can occur while decoding packets.
 #[derive(Debug)]
 pub enum PacketError {
+    /// The packet was shorter than the minimum or longer than the maximum packet length.
     InvalidLength,
+    /// The packet type is not defined for the side decoding it.
     UndefinedType,
+    /// A BattlEye packet didn't start with the `BE` header.
+    InvalidHeader,
+    /// A BattlEye packet's checksum didn't match its contents.
+    InvalidChecksum,
+    /// An io error from the underlying stream.
     Io(IoError),
 }
 
@@ -63,175 +103,219 @@ impl Display for PacketError {
                 write!(f, "Invalid Packet Length")
             }
             PacketError::UndefinedType => {
-                write!(f, "Undefined Packet Type ")
+                write!(f, "Undefined Packet Type")
+            }
+            PacketError::InvalidHeader => {
+                write!(f, "Invalid Packet Header")
+            }
+            PacketError::InvalidChecksum => {
+                write!(f, "Invalid Packet Checksum")
             }
         }
     }
 }
 
-impl Error for PacketError {}
+impl Error for PacketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PacketError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
+/// A single rcon packet.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packet {
+    /// The packet type.
     pub ptype: PacketType,
+    /// The id used to match responses to requests.
     pub id: i32,
+    /// The packet body.
     pub body: String,
 }
 
+/// Splits a packet without its size prefix into its id, type and raw body, stripping up to two trailing nulls.
+pub(crate) fn split_frame(mut b: Bytes, codec: CodecType) -> Result<(i32, PacketType, Bytes)> {
+    if b.remaining() < 8 {
+        return Err(PacketError::InvalidLength);
+    }
+    let id = b.get_i32_le();
+    let ptype = PacketType::from_i32(b.get_i32_le(), codec).ok_or(PacketError::UndefinedType)?;
+    for _ in 0..2 {
+        if b.last() == Some(&0) {
+            b.truncate(b.len() - 1);
+        }
+    }
+    Ok((id, ptype, b))
+}
+
 impl Packet {
     /// does not include the size part of the packet, that is removed by the codec
-    pub fn from_bytes(mut b: Bytes, codec: CodecType) -> Result<Packet> {
-        if !(10..=4096).contains(&b.remaining()) {
-            return Err(PacketError::InvalidLength);
-        }
-        let msg_id = b.get_i32_le();
-        let ptype =
-            PacketType::from_i32(b.get_i32_le(), codec).ok_or(PacketError::UndefinedType)?;
-
-        let mut body = String::new();
-        let rem = b.remaining() - 2;
-        b.take(rem)
-            .reader()
-            .read_to_string(&mut body)
-            .expect("failed to read bytes");
+    /// the maximum length is enforced by the codec, so it can be raised for servers which need it
+    /// Up to two trailing nulls are stripped, so packets from peers which send fewer terminators still decode,
+    /// and invalid UTF-8 in the body is replaced rather than failing the packet.
+    pub fn from_bytes(b: Bytes, codec: CodecType) -> Result<Packet> {
+        let (id, ptype, body) = split_frame(b, codec)?;
         Ok(Packet {
             ptype,
-            id: msg_id,
-            body,
+            id,
+            body: String::from_utf8_lossy(&body).into_owned(),
         })
     }
 
+    /// The length of the packet once encoded, not including the size prefix.
     pub fn encoded_len(&self) -> usize {
         &self.body.len() + 10
     }
 
-    pub fn write_bytes(self, buf: &mut BytesMut) -> usize {
+    /// Writes the packet without the size prefix, returning the number of bytes written.
+    pub fn write_bytes(&self, buf: &mut BytesMut) -> usize {
+        buf.reserve(self.encoded_len());
         buf.put_i32_le(self.id);
         buf.put_i32_le(self.ptype.bytes());
         buf.put_slice(self.body.as_bytes());
         buf.put_slice(&[0x00, 0x00]);
-        &self.body.len() + 10
+        self.encoded_len()
+    }
+
+    /// The packet as it goes on the wire, size prefix included, as a codec writes it but without a maximum length.
+    /// Panics if it is too long for the size prefix, over `i32::MAX` bytes.
+    pub fn to_wire_bytes(&self) -> Bytes {
+        let len = self.encoded_len();
+        let prefix = i32::try_from(len).expect("packet length doesn't fit in the size prefix");
+        let mut buf = BytesMut::with_capacity(len + 4);
+        buf.put_i32_le(prefix);
+        self.write_bytes(&mut buf);
+        buf.freeze()
+    }
+
+    /// Splits the packet into packets with the same id and type whose bodies are at most `max_len` bytes,
+    /// only splitting between characters. A character longer than `max_len` is kept whole in its own packet.
+    pub fn truncated_to(&self, max_len: usize) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut rest = self.body.as_str();
+        loop {
+            let mut end = rest.len().min(max_len);
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 && !rest.is_empty() {
+                end = rest.chars().next().map_or(0, char::len_utf8);
+            }
+            let (body, tail) = rest.split_at(end);
+            packets.push(Packet {
+                ptype: self.ptype,
+                id: self.id,
+                body: body.to_owned(),
+            });
+            if tail.is_empty() {
+                return packets;
+            }
+            rest = tail;
+        }
     }
 }
+impl From<&Packet> for Bytes {
+    /// See [`Packet::to_wire_bytes`].
+    fn from(p: &Packet) -> Bytes {
+        p.to_wire_bytes()
+    }
+}
+
 // https://developer.valvesoftware.com/wiki/Source_RCON_Protocol#Packet_Size
 // the rcon spec says that packets cannot be more than 4096 bytes
 
+/// A codec for framing rcon packets on tokio streams, a thin wrapper around [`PacketFramer`].
+#[cfg(feature = "runtime")]
 pub struct PacketCodec {
-    state: DecodeState,
-    ctype: CodecType,
-    max_length: usize,
+    framer: PacketFramer,
 }
 
+#[cfg(feature = "runtime")]
 impl PacketCodec {
     /// Creates a new PacketCodec,
     /// WARNING: The [RCON spec](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol#Packet_Size) sets a maximum packet size of 4096 bytes, raising it higher may cause issues with some clients.
     pub fn new(codec_type: CodecType, max_length: usize) -> PacketCodec {
         PacketCodec {
-            state: DecodeState::Head,
-            ctype: codec_type,
-            max_length,
+            framer: PacketFramer::new(codec_type, max_length),
         }
     }
 
+    /// The maximum length of a packet, not including the size prefix.
+    pub fn max_length(&self) -> usize {
+        self.framer.max_length()
+    }
+
+    /// The number of inbound packets which were skipped for being longer than `max_length`.
+    pub fn skipped_packets(&self) -> u64 {
+        self.framer.skipped_packets()
+    }
+
+    /// The framer underneath, for packets the codec can't represent, like bodies which aren't text.
+    pub(crate) fn framer_mut(&mut self) -> &mut PacketFramer {
+        &mut self.framer
+    }
+
+    /// Creates a client side codec with the spec's maximum packet size.
     #[cfg(feature = "client")]
     pub fn new_client() -> PacketCodec {
         Self::new(CodecType::Client, 4096)
     }
 
+    /// Creates a server side codec with the spec's maximum packet size.
     #[cfg(feature = "server")]
     pub fn new_server() -> PacketCodec {
         Self::new(CodecType::Server, 4096)
     }
 }
-#[derive(PartialEq, Clone, Copy)]
+
+/// Which side of the connection a codec is decoding for.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CodecType {
+    /// Decodes packets sent by a server.
     Client,
+    /// Decodes packets sent by a client.
     Server,
+    /// Decodes packets going either way, for proxies. `AuthResponse` and `ExecCommand` share the wire value 2,
+    /// so both decode as [`PacketType::Ambiguous`] and are encoded unchanged; use [`PacketType::resolve`]
+    /// with the direction the packet was going to tell them apart.
+    Proxy,
 }
 
-enum DecodeState {
-    Head,
-    Data(usize),
-    // used when invalid data is recieved and should be ignored.
-    // example: packets longer than 4096 bytes.
-    Ignore(usize),
-}
-
+#[cfg(feature = "runtime")]
 impl Encoder<Packet> for PacketCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> io::Result<()> {
-        dst.put_i32_le(item.encoded_len() as i32);
-        item.write_bytes(dst);
-        Ok(())
+        self.framer.encode(&item, dst)
     }
 }
 
+#[cfg(feature = "runtime")]
 impl Decoder for PacketCodec {
     type Item = Packet;
     type Error = PacketError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
-        if src.is_empty() {
-            return Ok(None);
-        }
-
-        let packet_len = match self.state {
-            DecodeState::Head => {
-                // ensures that src.get_i32_le() doesnt panic
-                if src.len() <= 4 {
-                    return Ok(None);
-                };
-                let packet_len = src.get_i32_le() as usize;
-                if src.len() > self.max_length {
-                    if src.len() >= packet_len {
-                        let _ = src.split_to(packet_len);
-                        return Ok(None);
-                    } else {
-                        let remaining = src.len();
-                        src.clear();
-                        self.state = DecodeState::Ignore(remaining);
-                        return Ok(None);
-                    }
-                    //return Err(Error::new(ErrorKind::InvalidData, "client sent a packet which was larger than the maximum allowed packet length"));
-                } else if src.len() < packet_len {
-                    self.state = DecodeState::Data(packet_len);
-                    return Ok(None);
-                }
-                packet_len
-            }
-            DecodeState::Data(packet_len) => {
-                if packet_len < src.len() {
-                    return Ok(None);
-                }
-                packet_len
-            }
-            DecodeState::Ignore(remaining) => {
-                if !src.is_empty() {
-                    if src.len() >= remaining {
-                        let _ = src.split_to(remaining);
-                        self.state = DecodeState::Head;
-                    } else {
-                        self.state = DecodeState::Ignore(remaining - src.len());
-                        src.clear();
-                    }
-                }
-                return Ok(None);
-            }
-        };
-        let data = src.split_to(packet_len).freeze();
-        Ok(Some(Packet::from_bytes(data, self.ctype)?))
+        self.framer.decode(src)
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "runtime"))]
 mod tests {
     use super::*;
+    use std::io::ErrorKind;
     //use bytes::*;
     // the packet type does not
     static AUTH_PACKET: [u8; 18] = [
         10, 0, 0, 0, 3, 0, 0, 0, 112, 97, 115, 115, 119, 111, 114, 100, 0, 0,
     ];
+    static AUTH_PACKET_WITH_LEN: [u8; 22] = [
+        18, 0, 0, 0, 10, 0, 0, 0, 3, 0, 0, 0, 112, 97, 115, 115, 119, 111, 114, 100, 0, 0,
+    ];
     static EMPTY_AUTH_PACKET: [u8; 10] = [10, 0, 0, 0, 3, 0, 0, 0, 0, 0];
 
     #[tokio::test]
@@ -259,8 +343,11 @@ mod tests {
 
         let mut packet_bytes = BytesMut::new();
         let mut test_bytes = BytesMut::new();
+        assert_eq!(packet.write_bytes(&mut packet_bytes), AUTH_PACKET.len());
+        // the packet is only borrowed, so it can be written again
         packet.write_bytes(&mut packet_bytes);
         test_bytes.put_slice(&AUTH_PACKET);
+        test_bytes.put_slice(&AUTH_PACKET);
         assert_eq!(packet_bytes.freeze(), test_bytes.freeze());
     }
 
@@ -284,4 +371,239 @@ mod tests {
 
     #[tokio::test]
     async fn exec_packet_decode() {}
+
+    #[test]
+    fn encode_rejects_packets_over_max_length() {
+        let mut codec = PacketCodec::new(CodecType::Client, 4096);
+        let mut buf = BytesMut::new();
+        let packet = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 1,
+            body: "a".repeat(5000),
+        };
+        let err = codec.encode(packet, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+
+        let packet = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 1,
+            body: "a".repeat(4086),
+        };
+        codec.encode(packet, &mut buf).unwrap();
+        assert_eq!(buf.len(), 4100);
+    }
+
+    #[test]
+    fn encode_writes_the_encoded_length_as_the_size_prefix() {
+        // a raised max_length, where the prefix is the only thing checking the length
+        let mut codec = PacketCodec::new(CodecType::Client, usize::MAX);
+        let mut buf = BytesMut::new();
+        let packet = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 1,
+            body: "a".repeat(70_000),
+        };
+        let len = packet.encoded_len();
+        codec.encode(packet, &mut buf).unwrap();
+        assert_eq!(buf.len(), len + 4);
+        assert_eq!(buf.get_i32_le(), 70_010);
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn oversize_packet_is_skipped_and_counted() {
+        let mut codec = PacketCodec::new(CodecType::Server, 18);
+        let mut buf = BytesMut::new();
+        buf.put_i32_le(20);
+        buf.put_slice(&[0; 20]);
+        buf.put_slice(&AUTH_PACKET_WITH_LEN);
+
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(PacketError::InvalidLength)
+        ));
+        assert_eq!(codec.skipped_packets(), 1);
+        let p = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(p.body, "password");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn partial_oversize_packet_is_skipped_and_counted() {
+        let mut codec = PacketCodec::new(CodecType::Server, 18);
+        let mut buf = BytesMut::new();
+        buf.put_i32_le(20);
+        buf.put_slice(&[0; 8]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        buf.put_slice(&[0; 12]);
+        buf.put_slice(&AUTH_PACKET_WITH_LEN);
+        let p = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(p.body, "password");
+        assert_eq!(codec.skipped_packets(), 1);
+    }
+
+    #[test]
+    fn fragmented_packet_is_decoded() {
+        let mut codec = PacketCodec::new(CodecType::Server, 4096);
+        let mut buf = BytesMut::new();
+        for chunk in AUTH_PACKET_WITH_LEN.chunks(3) {
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            buf.put_slice(chunk);
+        }
+        let p = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(p.body, "password");
+    }
+
+    /// Feeds `len` as the length prefix followed by `trailing` zeroes and a valid packet.
+    fn decode_after_length(
+        len: i32,
+        trailing: usize,
+    ) -> (Result<Option<Packet>>, PacketCodec, BytesMut) {
+        let mut codec = PacketCodec::new(CodecType::Server, 4096);
+        let mut buf = BytesMut::new();
+        buf.put_i32_le(len);
+        buf.put_slice(&vec![0; trailing]);
+        buf.put_slice(&AUTH_PACKET_WITH_LEN);
+        (codec.decode(&mut buf), codec, buf)
+    }
+
+    #[test]
+    fn negative_length_is_rejected() {
+        let (res, mut codec, mut buf) = decode_after_length(-1, 0);
+        assert!(matches!(res, Err(PacketError::InvalidLength)));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().body, "password");
+    }
+
+    #[test]
+    fn zero_length_is_rejected() {
+        let (res, mut codec, mut buf) = decode_after_length(0, 0);
+        assert!(matches!(res, Err(PacketError::InvalidLength)));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().body, "password");
+
+        let (res, mut codec, mut buf) = decode_after_length(9, 9);
+        assert!(matches!(res, Err(PacketError::InvalidLength)));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().body, "password");
+    }
+
+    #[test]
+    fn max_length_is_rejected_and_ignored() {
+        let (res, mut codec, mut buf) = decode_after_length(i32::MAX, 0);
+        assert!(matches!(res, Err(PacketError::InvalidLength)));
+        assert!(buf.is_empty());
+        // everything up to the declared length is discarded
+        buf.put_slice(&AUTH_PACKET_WITH_LEN);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn packet_serde_round_trip() {
+        let packet = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 10,
+            body: String::from("list"),
+        };
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(json, r#"{"ptype":"ExecCommand","id":10,"body":"list"}"#);
+        assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+
+        let codec = serde_json::to_string(&CodecType::Server).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CodecType>(&codec).unwrap(),
+            CodecType::Server
+        );
+    }
+
+    #[test]
+    fn from_bytes_tolerates_missing_terminators() {
+        for nulls in 0..=2 {
+            let mut b = vec![7, 0, 0, 0, 0, 0, 0, 0];
+            b.extend_from_slice(b"hello");
+            b.resize(b.len() + nulls, 0);
+            let p = Packet::from_bytes(Bytes::from(b), CodecType::Client).unwrap();
+            assert_eq!((p.id, p.body.as_str()), (7, "hello"), "{} nulls", nulls);
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated_header() {
+        let b = Bytes::from_static(&[7, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(
+            Packet::from_bytes(b, CodecType::Client),
+            Err(PacketError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn from_bytes_replaces_invalid_utf8() {
+        let b = Bytes::from_static(&[7, 0, 0, 0, 0, 0, 0, 0, b'h', 0xFF, b'i', 0, 0]);
+        let p = Packet::from_bytes(b, CodecType::Client).unwrap();
+        assert_eq!(p.body, "h\u{FFFD}i");
+    }
+
+    #[test]
+    fn truncated_to_splits_between_characters() {
+        let packet = Packet {
+            ptype: PacketType::ResponseValue,
+            id: 3,
+            body: "abc\u{1F600}def\u{1F600}".to_owned(),
+        };
+        let parts = packet.truncated_to(5);
+        let bodies: Vec<&str> = parts.iter().map(|p| p.body.as_str()).collect();
+        assert_eq!(bodies, ["abc", "\u{1F600}d", "ef", "\u{1F600}"]);
+        assert!(parts
+            .iter()
+            .all(|p| p.id == 3 && p.ptype == PacketType::ResponseValue));
+        assert!(parts.iter().all(|p| p.body.len() <= 5));
+
+        assert_eq!(packet.truncated_to(4096), vec![packet.clone()]);
+        assert_eq!(packet.truncated_to(2).len(), 6);
+    }
+
+    #[test]
+    fn proxy_codec_leaves_type_two_ambiguous() {
+        let b = Bytes::from_static(&[7, 0, 0, 0, 2, 0, 0, 0, b'h', b'i', 0, 0]);
+        let p = Packet::from_bytes(b, CodecType::Proxy).unwrap();
+        assert_eq!(
+            (p.ptype, p.id, p.body.as_str()),
+            (PacketType::Ambiguous, 7, "hi")
+        );
+        assert_eq!(p.ptype.bytes(), 2);
+        assert_eq!(p.ptype.resolve(CodecType::Client), PacketType::AuthResponse);
+        assert_eq!(p.ptype.resolve(CodecType::Server), PacketType::ExecCommand);
+        assert_eq!(
+            PacketType::Auth.resolve(CodecType::Client),
+            PacketType::Auth
+        );
+
+        let mut buf = BytesMut::new();
+        p.write_bytes(&mut buf);
+        assert_eq!(&buf[..], &[7, 0, 0, 0, 2, 0, 0, 0, b'h', b'i', 0, 0]);
+    }
+
+    #[test]
+    fn wire_bytes_match_the_codec() {
+        let packet = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 9,
+            body: "say hi".to_owned(),
+        };
+        let mut encoded = BytesMut::new();
+        PacketCodec::new(CodecType::Client, 4096)
+            .encode(packet.clone(), &mut encoded)
+            .unwrap();
+        assert_eq!(packet.to_wire_bytes(), encoded.freeze());
+        assert_eq!(Bytes::from(&packet), packet.to_wire_bytes());
+        assert_eq!(
+            &Bytes::from(&Packet {
+                ptype: PacketType::Auth,
+                id: 10,
+                body: "password".to_owned(),
+            })[..],
+            &AUTH_PACKET_WITH_LEN[..]
+        );
+    }
 }
@@ -0,0 +1,123 @@
+use super::command::{Command, Escaping};
+use std::borrow::Cow;
+
+/// How a profile rewrites the start of commands before they are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandPrefix {
+    /// Commands are sent unchanged.
+    Keep,
+    /// The prefix is removed from commands starting with it.
+    Strip(String),
+    /// The prefix is added to commands not starting with it.
+    Require(String),
+}
+
+/// Which commands `Connection::server_info` runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InfoQuery {
+    None,
+    Minecraft,
+    Source,
+}
+
+/// Behaviour specific to a kind of server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerProfile {
+    command_prefix: CommandPrefix,
+    escaping: Escaping,
+    info: InfoQuery,
+}
+
+impl Default for ServerProfile {
+    fn default() -> Self {
+        Self::generic()
+    }
+}
+
+impl ServerProfile {
+    /// A profile which sends commands unchanged and escapes [`Command`] arguments with backslashes.
+    pub fn generic() -> Self {
+        ServerProfile {
+            command_prefix: CommandPrefix::Keep,
+            escaping: Escaping::Minecraft,
+            info: InfoQuery::None,
+        }
+    }
+
+    /// A profile for Minecraft servers, which strips the leading `/` players type in chat but rcon doesn't expect.
+    pub fn minecraft() -> Self {
+        ServerProfile {
+            command_prefix: CommandPrefix::Strip("/".to_owned()),
+            escaping: Escaping::Minecraft,
+            info: InfoQuery::Minecraft,
+        }
+    }
+
+    /// A profile for Source engine servers, which sends commands unchanged.
+    pub fn source() -> Self {
+        ServerProfile {
+            command_prefix: CommandPrefix::Keep,
+            escaping: Escaping::Source,
+            info: InfoQuery::Source,
+        }
+    }
+
+    /// Overrides how the profile rewrites the start of commands.
+    pub fn command_prefix(mut self, command_prefix: CommandPrefix) -> Self {
+        self.command_prefix = command_prefix;
+        self
+    }
+
+    /// Overrides how [`Command`] arguments are escaped.
+    pub fn escaping(mut self, escaping: Escaping) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// Renders `cmd` with the profile's escaping.
+    pub fn render(&self, cmd: &Command) -> String {
+        cmd.render(self.escaping)
+    }
+
+    pub(crate) fn info_query(&self) -> InfoQuery {
+        self.info
+    }
+
+    /// Applies the profile's command prefix rule to `cmd`.
+    pub fn normalize<'a>(&self, cmd: &'a str) -> Cow<'a, str> {
+        match &self.command_prefix {
+            CommandPrefix::Keep => Cow::Borrowed(cmd),
+            CommandPrefix::Strip(prefix) => {
+                Cow::Borrowed(cmd.strip_prefix(prefix.as_str()).unwrap_or(cmd))
+            }
+            CommandPrefix::Require(prefix) if cmd.starts_with(prefix.as_str()) => {
+                Cow::Borrowed(cmd)
+            }
+            CommandPrefix::Require(prefix) => Cow::Owned(format!("{}{}", prefix, cmd)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minecraft_strips_slash() {
+        let p = ServerProfile::minecraft();
+        assert_eq!(p.normalize("/list"), "list");
+        assert_eq!(p.normalize("list"), "list");
+    }
+
+    #[test]
+    fn required_prefix_is_added_once() {
+        let p = ServerProfile::generic().command_prefix(CommandPrefix::Require("/".to_owned()));
+        assert_eq!(p.normalize("list"), "/list");
+        assert_eq!(p.normalize("/list"), "/list");
+    }
+
+    #[test]
+    fn generic_keeps_commands() {
+        assert_eq!(ServerProfile::generic().normalize("/list"), "/list");
+    }
+}
@@ -0,0 +1,272 @@
+use super::packet::{split_frame, CodecType, Packet, PacketError, PacketType};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+use std::io::{self, Error as IoError, ErrorKind};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+/// Frames rcon packets on plain byte buffers, with no IO or runtime involved.
+/// [`PacketCodec`](super::packet::PacketCodec) wraps it for tokio, other event loops can feed it bytes as they
+/// arrive and write out what it encodes.
+/// ```
+/// use bytes::BytesMut;
+/// use rcon_rs::packet::{CodecType, Packet, PacketType};
+/// use rcon_rs::sans_io::PacketFramer;
+///
+/// let client = PacketFramer::new(CodecType::Client, 4096);
+/// let mut server = PacketFramer::new(CodecType::Server, 4096);
+/// let mut wire = BytesMut::new();
+/// let p = Packet { ptype: PacketType::ExecCommand, id: 1, body: "list".into() };
+/// client.encode(&p, &mut wire).unwrap();
+/// assert_eq!(server.decode(&mut wire).unwrap(), Some(p));
+/// ```
+pub struct PacketFramer {
+    state: DecodeState,
+    ctype: CodecType,
+    max_length: usize,
+    skipped: u64,
+}
+
+enum DecodeState {
+    Head,
+    Data(usize),
+    // used when invalid data is recieved and should be ignored.
+    // example: packets longer than 4096 bytes.
+    Ignore(usize),
+}
+
+/// Converts an encoded length to the size prefix, which must fit in a positive i32.
+fn length_prefix(len: usize) -> io::Result<i32> {
+    i32::try_from(len).map_err(|_| {
+        IoError::new(
+            ErrorKind::InvalidInput,
+            format!("packet length {} doesn't fit in the size prefix", len),
+        )
+    })
+}
+
+impl PacketFramer {
+    /// Creates a framer decoding packets for the given side, skipping inbound packets longer than `max_length`.
+    /// WARNING: The [RCON spec](https://developer.valvesoftware.com/wiki/Source_RCON_Protocol#Packet_Size) sets a maximum packet size of 4096 bytes, raising it higher may cause issues with some clients.
+    pub fn new(ctype: CodecType, max_length: usize) -> PacketFramer {
+        PacketFramer {
+            state: DecodeState::Head,
+            ctype,
+            max_length,
+            skipped: 0,
+        }
+    }
+
+    /// The maximum length of a packet, not including the size prefix.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// The number of inbound packets which were skipped for being longer than `max_length`.
+    pub fn skipped_packets(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Takes the next packet from the front of `buf`, returning `None` until all of it has arrived.
+    /// An error skips the bad packet, so decoding can carry on with the rest of the buffer.
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Packet>, PacketError> {
+        match self.next_frame(buf)? {
+            Some(data) => Ok(Some(Packet::from_bytes(data, self.ctype)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`decode`](Self::decode), but keeps the body as raw bytes for bodies which aren't text,
+    /// returning the packet's id, type and body with up to two trailing nulls stripped.
+    pub fn decode_bytes(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> Result<Option<(i32, PacketType, Bytes)>, PacketError> {
+        match self.next_frame(buf)? {
+            Some(data) => Ok(Some(split_frame(data, self.ctype)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes the next packet from the front of `buf` without its size prefix.
+    fn next_frame(&mut self, buf: &mut BytesMut) -> Result<Option<Bytes>, PacketError> {
+        if let DecodeState::Ignore(remaining) = self.state {
+            self.ignore(buf, remaining);
+            if let DecodeState::Ignore(_) = self.state {
+                return Ok(None);
+            }
+        }
+
+        let packet_len = match self.state {
+            DecodeState::Head => {
+                // ensures that buf.get_i32_le() doesnt panic
+                if buf.len() < 4 {
+                    return Ok(None);
+                };
+                let declared = buf.get_i32_le();
+                if declared < 10 {
+                    debug!(
+                        "rejecting inbound packet with a declared length of {}",
+                        declared
+                    );
+                    if declared > 0 {
+                        self.ignore(buf, declared as usize);
+                    }
+                    return Err(PacketError::InvalidLength);
+                }
+                let packet_len = declared as usize;
+                if packet_len > self.max_length {
+                    self.skipped += 1;
+                    debug!(
+                        "skipping inbound packet with a declared length of {} bytes, the max_length is {} (the spec limit is 4096)",
+                        packet_len, self.max_length
+                    );
+                    self.ignore(buf, packet_len);
+                    return Err(PacketError::InvalidLength);
+                }
+                packet_len
+            }
+            DecodeState::Data(packet_len) => packet_len,
+            DecodeState::Ignore(_) => unreachable!("ignored bytes are consumed above"),
+        };
+        if buf.len() < packet_len {
+            self.state = DecodeState::Data(packet_len);
+            return Ok(None);
+        }
+        self.state = DecodeState::Head;
+        Ok(Some(buf.split_to(packet_len).freeze()))
+    }
+
+    /// Appends `pkt` with its size prefix to `out`, failing if it is longer than `max_length`.
+    pub fn encode(&self, pkt: &Packet, out: &mut BytesMut) -> io::Result<()> {
+        self.encode_bytes(pkt.ptype, pkt.id, pkt.body.as_bytes(), out)
+    }
+
+    /// Like [`encode`](Self::encode), but takes the body as raw bytes for bodies which aren't text.
+    pub fn encode_bytes(
+        &self,
+        ptype: PacketType,
+        id: i32,
+        body: &[u8],
+        out: &mut BytesMut,
+    ) -> io::Result<()> {
+        let len = body.len() + 10;
+        if len > self.max_length {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "packet length {} is longer than the max_length of {}",
+                    len, self.max_length
+                ),
+            ));
+        }
+        out.reserve(len + 4);
+        out.put_i32_le(length_prefix(len)?);
+        out.put_i32_le(id);
+        out.put_i32_le(ptype.bytes());
+        out.put_slice(body);
+        out.put_slice(&[0x00, 0x00]);
+        Ok(())
+    }
+
+    /// Discards `len` bytes, including ones which haven't been received yet.
+    fn ignore(&mut self, buf: &mut BytesMut, len: usize) {
+        if buf.len() >= len {
+            buf.advance(len);
+            self.state = DecodeState::Head;
+        } else {
+            self.state = DecodeState::Ignore(len - buf.len());
+            buf.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketType;
+
+    #[test]
+    fn length_prefix_rejects_overflow() {
+        assert_eq!(length_prefix(18).unwrap(), 18);
+        assert_eq!(length_prefix(i32::MAX as usize).unwrap(), i32::MAX);
+        let err = length_prefix(i32::MAX as usize + 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(length_prefix(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn round_trips_a_stream_of_packets() {
+        let client = PacketFramer::new(CodecType::Client, 4096);
+        let mut server = PacketFramer::new(CodecType::Server, 4096);
+        let packets: Vec<_> = (1..=3)
+            .map(|id| Packet {
+                ptype: PacketType::ExecCommand,
+                id,
+                body: format!("cmd {}", id),
+            })
+            .collect();
+        let mut wire = BytesMut::new();
+        for p in &packets {
+            client.encode(p, &mut wire).unwrap();
+        }
+
+        // the bytes arrive a few at a time
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in wire.chunks(5) {
+            buf.extend_from_slice(chunk);
+            while let Some(p) = server.decode(&mut buf).unwrap() {
+                decoded.push(p);
+            }
+        }
+        assert_eq!(decoded, packets);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn round_trips_bodies_which_arent_text() {
+        let client = PacketFramer::new(CodecType::Client, 4096);
+        let mut server = PacketFramer::new(CodecType::Server, 4096);
+        let mut wire = BytesMut::new();
+        client
+            .encode_bytes(PacketType::ExecCommand, 7, &[0xff, b'a', 0xfe], &mut wire)
+            .unwrap();
+        let (id, ptype, body) = server.decode_bytes(&mut wire).unwrap().unwrap();
+        assert_eq!((id, ptype), (7, PacketType::ExecCommand));
+        assert_eq!(&body[..], &[0xff, b'a', 0xfe]);
+        assert!(wire.is_empty());
+
+        let err = client
+            .encode_bytes(PacketType::ExecCommand, 8, &[0; 4087], &mut wire)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(wire.is_empty());
+    }
+
+    #[test]
+    fn skips_oversize_packets() {
+        let mut framer = PacketFramer::new(CodecType::Client, 32);
+        let mut buf = BytesMut::new();
+        buf.put_i32_le(64);
+        buf.put_slice(&[0; 64]);
+        PacketFramer::new(CodecType::Server, 32)
+            .encode(
+                &Packet {
+                    ptype: PacketType::ResponseValue,
+                    id: 2,
+                    body: "ok".into(),
+                },
+                &mut buf,
+            )
+            .unwrap();
+
+        assert!(matches!(
+            framer.decode(&mut buf),
+            Err(PacketError::InvalidLength)
+        ));
+        assert_eq!(framer.skipped_packets(), 1);
+        assert_eq!(framer.decode(&mut buf).unwrap().unwrap().body, "ok");
+    }
+}
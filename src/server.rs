@@ -1,42 +1,517 @@
 use async_trait::async_trait;
-use futures::{FutureExt, SinkExt, StreamExt};
-use log::*;
-use std::{collections::HashMap, io, sync::Arc};
+use futures::{SinkExt, StreamExt};
+#[cfg(not(feature = "tracing"))]
+use log::{debug, error, warn};
+#[cfg(all(unix, feature = "unix"))]
+use std::path::Path;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+#[cfg(all(unix, feature = "unix"))]
+use tokio::net::UnixListener;
 use tokio::{
     net::{TcpListener, TcpStream, ToSocketAddrs},
-    sync::{Mutex, RwLock},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch, Mutex, RwLock,
+    },
+    task::JoinHandle,
+    time::{sleep_until, timeout, Instant},
 };
+/// The rustls version [`RconServer::run_tls`] takes its config from.
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::*;
-pub use typemap::TypeMap;
+#[cfg(feature = "tracing")]
+use tracing::{debug, error, warn};
+pub use typemap::{Key, ShareMap};
 
 use super::*;
 use packet::*;
+use transport::{set_tcp_keepalive, Transport};
+
+mod metrics;
+mod rate_limit;
+pub use metrics::{CounterMetrics, ServerMetrics};
+use rate_limit::TokenBucket;
 
+/// The handler for a single session.
+/// A new instance is created for every accepted connection.
 #[async_trait]
 pub trait RconImpl {
-    fn new(state: Arc<RwLock<TypeMap>>) -> Self;
+    /// Creates the handler for a new session, with access to the server's shared state.
+    fn new(state: Arc<RwLock<ShareMap>>) -> Self;
+    /// Checks the password sent by the client, returning whether it was accepted.
     async fn authenticate(&mut self, password: String, pid: i32) -> bool; // change this return Result<bool, anyhow::Error>
+    /// Runs a command, returning the response body.
+    /// Errors are sent to the client as a body starting with [`ERROR_PREFIX`].
     async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error>;
 }
 
+/// Which handler a [`ModeSwitch`] is delegating to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Mode {
+    /// Commands are handled normally.
+    #[default]
+    Normal,
+    /// Commands are handled by the maintenance handler.
+    Maintenance,
+}
+
+/// The shared state key holding the active [`Mode`].
+pub struct ActiveMode;
+
+impl Key for ActiveMode {
+    type Value = Mode;
+}
+
+/// A `RconImpl` which delegates to `N` or `M` depending on the [`ActiveMode`] in the shared state.
+/// The mode is checked on every call, so switching it affects existing sessions without dropping them.
+pub struct ModeSwitch<N, M> {
+    state: Arc<RwLock<ShareMap>>,
+    normal: N,
+    maintenance: M,
+}
+
+impl<N, M> ModeSwitch<N, M> {
+    /// Sets the active mode for every session sharing `state`.
+    pub async fn set_mode(state: &RwLock<ShareMap>, mode: Mode) {
+        state.write().await.insert::<ActiveMode>(mode);
+    }
+
+    /// Gets the active mode, defaulting to `Mode::Normal`.
+    pub async fn mode(state: &RwLock<ShareMap>) -> Mode {
+        state
+            .read()
+            .await
+            .get::<ActiveMode>()
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl<N, M> RconImpl for ModeSwitch<N, M>
+where
+    N: RconImpl + Send,
+    M: RconImpl + Send,
+{
+    fn new(state: Arc<RwLock<ShareMap>>) -> Self {
+        ModeSwitch {
+            normal: N::new(Arc::clone(&state)),
+            maintenance: M::new(Arc::clone(&state)),
+            state,
+        }
+    }
+
+    async fn authenticate(&mut self, password: String, pid: i32) -> bool {
+        match Self::mode(&self.state).await {
+            Mode::Normal => self.normal.authenticate(password, pid).await,
+            Mode::Maintenance => self.maintenance.authenticate(password, pid).await,
+        }
+    }
+
+    async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+        match Self::mode(&self.state).await {
+            Mode::Normal => self.normal.process(cmd).await,
+            Mode::Maintenance => self.maintenance.process(cmd).await,
+        }
+    }
+}
+
+/// The shared state key holding the password accepted by [`PasswordAuth`].
+pub struct AcceptedPassword;
+
+impl Key for AcceptedPassword {
+    type Value = String;
+}
+
+/// A `RconImpl` which accepts the [`AcceptedPassword`] in the shared state, and `T`'s own check, then
+/// delegates commands to `T`. The password is checked on every login, so changing it with
+/// [`set_password`](Self::set_password) takes effect straight away while logged in sessions stay logged in.
+/// No password is accepted until one is set.
+pub struct PasswordAuth<T> {
+    state: Arc<RwLock<ShareMap>>,
+    inner: T,
+}
+
+impl<T> PasswordAuth<T> {
+    /// Sets the password accepted by new logins for every session sharing `state`.
+    pub async fn set_password<P: ToString>(state: &RwLock<ShareMap>, password: P) {
+        state
+            .write()
+            .await
+            .insert::<AcceptedPassword>(password.to_string());
+    }
+}
+
+#[async_trait]
+impl<T: RconImpl + Send> RconImpl for PasswordAuth<T> {
+    fn new(state: Arc<RwLock<ShareMap>>) -> Self {
+        PasswordAuth {
+            inner: T::new(Arc::clone(&state)),
+            state,
+        }
+    }
+
+    async fn authenticate(&mut self, password: String, pid: i32) -> bool {
+        let accepted = self.state.read().await.get::<AcceptedPassword>() == Some(&password);
+        accepted && self.inner.authenticate(password, pid).await
+    }
+
+    async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+        self.inner.process(cmd).await
+    }
+}
+
+/// A `RconImpl` which answers every command with the command itself, for trying out the server and for tests.
+/// It accepts the [`AcceptedPassword`] in the shared state, set with [`PasswordAuth::set_password`], or any
+/// password if there isn't one.
+pub struct EchoImpl {
+    state: Arc<RwLock<ShareMap>>,
+}
+
+#[async_trait]
+impl RconImpl for EchoImpl {
+    fn new(state: Arc<RwLock<ShareMap>>) -> Self {
+        EchoImpl { state }
+    }
+
+    async fn authenticate(&mut self, password: String, _: i32) -> bool {
+        match self.state.read().await.get::<AcceptedPassword>() {
+            Some(accepted) => *accepted == password,
+            None => true,
+        }
+    }
+
+    async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+        Ok(cmd)
+    }
+}
+
+/// The result of a client's login attempt, passed to the [`RconServer::on_auth`] hook.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AuthOutcome {
+    /// The session logged in for the first time.
+    Success,
+    /// An already logged in session logged in again.
+    Reauthenticated,
+    /// The password was rejected, the session is no longer logged in.
+    Failure,
+}
+
+/// Called with the client's address, if it has one, after every login attempt.
+type AuthHook = Arc<dyn Fn(Option<SocketAddr>, AuthOutcome) + Send + Sync>;
+
+/// Called with the client's address, if it has one, and every command before it is run.
+type CommandHook = Arc<dyn Fn(Option<SocketAddr>, &str) + Send + Sync>;
+
+/// The default body sent for commands over the server's rate limit.
+pub const RATE_LIMITED: &str = "rate limited";
+
+/// The body of the packet sent to every session when the server is quiesced.
+/// It is sent as a `ResponseValue` with id 0, which doesn't belong to any request.
+pub const SHUTDOWN_NOTICE: &str = "server is shutting down";
+
+/// What [`RconServer::broadcast`] does with a session which has [`broadcast_buffer`](RconServer::broadcast_buffer)
+/// broadcasts waiting to be sent, because its client isn't reading them.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BroadcastOverflow {
+    /// Skip the broadcast for that session.
+    #[default]
+    Drop,
+    /// Disconnect the session.
+    Disconnect,
+}
+
+/// A rcon server that creates a `T` for every session.
+/// Clones share the same state and sessions, so one can be running while another quiesces it.
 pub struct RconServer<T: RconImpl> {
-    state: Arc<RwLock<TypeMap>>,
-    sessions: HashMap<i32, ServerSession<T>>, // change to vec with all sessions in it
+    state: Arc<RwLock<ShareMap>>,
+    control: Arc<Control>,
+    on_auth: Option<AuthHook>,
+    on_command: Option<CommandHook>,
+    metrics: Option<Arc<dyn ServerMetrics>>,
+    rate_limit: Option<TokenBucket>,
+    rate_limited_body: String,
+    auth_banner: Option<String>,
+    broadcast_buffer: usize,
+    broadcast_overflow: BroadcastOverflow,
+    auth_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    _impl: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T: RconImpl> Clone for RconServer<T> {
+    fn clone(&self) -> Self {
+        RconServer {
+            state: Arc::clone(&self.state),
+            control: Arc::clone(&self.control),
+            on_auth: self.on_auth.clone(),
+            on_command: self.on_command.clone(),
+            metrics: self.metrics.clone(),
+            rate_limit: self.rate_limit.clone(),
+            rate_limited_body: self.rate_limited_body.clone(),
+            auth_banner: self.auth_banner.clone(),
+            broadcast_buffer: self.broadcast_buffer,
+            broadcast_overflow: self.broadcast_overflow,
+            auth_timeout: self.auth_timeout,
+            tcp_keepalive: self.tcp_keepalive,
+            _impl: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Tracks the running sessions so they can be shut down together.
+struct Control {
+    /// Set to true to stop the accept loops and notify every session
+    shutdown: watch::Sender<bool>,
+    sessions: StdMutex<HashMap<u64, JoinHandle<()>>>,
+    /// The broadcast queue of every running session
+    broadcasts: StdMutex<HashMap<u64, mpsc::Sender<String>>>,
+    next_session: AtomicU64,
+}
+
+impl<T: RconImpl + std::marker::Send + 'static> Default for RconServer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T: RconImpl + std::marker::Send + 'static> RconServer<T> {
+    /// Creates a server with empty shared state.
     pub fn new() -> Self {
         RconServer {
-            state: Arc::new(RwLock::new(TypeMap::new())),
-            sessions: HashMap::new(),
+            state: Arc::new(RwLock::new(ShareMap::custom())),
+            control: Arc::new(Control {
+                shutdown: watch::channel(false).0,
+                sessions: StdMutex::new(HashMap::new()),
+                broadcasts: StdMutex::new(HashMap::new()),
+                next_session: AtomicU64::new(0),
+            }),
+            on_auth: None,
+            on_command: None,
+            metrics: None,
+            rate_limit: None,
+            rate_limited_body: RATE_LIMITED.to_owned(),
+            auth_banner: None,
+            broadcast_buffer: 64,
+            broadcast_overflow: BroadcastOverflow::Drop,
+            auth_timeout: None,
+            tcp_keepalive: None,
+            _impl: std::marker::PhantomData,
+        }
+    }
+
+    /// Calls `hook` after every login attempt, for audit logging. The address is `None` for Unix socket clients.
+    pub fn on_auth<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Option<SocketAddr>, AuthOutcome) + Send + Sync + 'static,
+    {
+        self.on_auth = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` with every command a logged in client runs, before it is passed to the [`RconImpl`], for an
+    /// audit trail. Commands over the rate limit aren't reported. The address is `None` for Unix socket clients.
+    pub fn on_command<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Option<SocketAddr>, &str) + Send + Sync + 'static,
+    {
+        self.on_command = Some(Arc::new(hook));
+        self
+    }
+
+    /// Reports sessions, commands and failed logins to `metrics`, such as a shared [`CounterMetrics`].
+    pub fn metrics(mut self, metrics: Arc<dyn ServerMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Limits each session to `max_per_interval` commands every `interval`, refilled evenly so bursts up to
+    /// the limit are allowed. Commands over the limit aren't passed to the [`RconImpl`], they are answered with
+    /// the [`rate_limited_body`](Self::rate_limited_body) instead, as a plain response any client can show.
+    pub fn rate_limit(mut self, max_per_interval: u32, interval: Duration) -> Self {
+        self.rate_limit = Some(TokenBucket::new(max_per_interval, interval));
+        self
+    }
+
+    /// Sets the body sent for commands over the rate limit, defaults to [`RATE_LIMITED`].
+    pub fn rate_limited_body<S: ToString>(mut self, body: S) -> Self {
+        self.rate_limited_body = body.to_string();
+        self
+    }
+
+    /// Sends `banner` as a `ResponseValue` with the auth request's id straight after every successful login,
+    /// for tools which show a message of the day. Clients which don't expect it have to skip it. Disabled by default.
+    pub fn auth_banner(mut self, banner: Option<String>) -> Self {
+        self.auth_banner = banner;
+        self
+    }
+
+    /// Sets how many broadcasts can wait to be sent to a session before the [`BroadcastOverflow`] policy applies,
+    /// so a client which stops reading can't use up the server's memory. Defaults to 64, and can't be 0.
+    pub fn broadcast_buffer(mut self, broadcasts: usize) -> Self {
+        self.broadcast_buffer = broadcasts.max(1);
+        self
+    }
+
+    /// Sets what happens to sessions which fall `broadcast_buffer` broadcasts behind, defaults to dropping broadcasts.
+    pub fn broadcast_overflow(mut self, overflow: BroadcastOverflow) -> Self {
+        self.broadcast_overflow = overflow;
+        self
+    }
+
+    /// Closes sessions which haven't logged in within `timeout` of connecting, so clients which never
+    /// authenticate don't hold connections open. Sessions which have logged in aren't timed out. Disabled by default.
+    pub fn auth_timeout(mut self, timeout: Duration) -> Self {
+        self.auth_timeout = Some(timeout);
+        self
+    }
+
+    /// Turns on TCP keepalive for accepted connections, so the OS notices dead clients after a connection has
+    /// been idle for `time`. Disabled by default.
+    pub fn tcp_keepalive(mut self, time: Option<Duration>) -> Self {
+        self.tcp_keepalive = time;
+        self
+    }
+
+    /// A handle to the state shared by every session, for seeding it before [`run`](Self::run)
+    /// or reading and changing it while the server is running.
+    pub fn state(&self) -> Arc<RwLock<ShareMap>> {
+        Arc::clone(&self.state)
+    }
+
+    /// Sends `body` to every logged in session as a `ResponseValue` with id 0, which doesn't belong to any request.
+    /// Returns how many sessions it was queued for.
+    pub fn broadcast<S: ToString>(&self, body: S) -> usize {
+        let body = body.to_string();
+        let mut queued = 0;
+        let mut overflowed = Vec::new();
+        for (id, tx) in self.control.broadcasts.lock().unwrap().iter() {
+            match tx.try_send(body.clone()) {
+                Ok(()) => queued += 1,
+                Err(TrySendError::Full(_)) => overflowed.push(*id),
+                // the session is ending and unregisters itself
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+        for id in overflowed {
+            match self.broadcast_overflow {
+                BroadcastOverflow::Drop => {
+                    warn!(
+                        "session {} isn't keeping up with broadcasts, dropping one",
+                        id
+                    )
+                }
+                BroadcastOverflow::Disconnect => {
+                    warn!(
+                        "session {} isn't keeping up with broadcasts, disconnecting it",
+                        id
+                    );
+                    self.control.broadcasts.lock().unwrap().remove(&id);
+                    if let Some(h) = self.control.sessions.lock().unwrap().remove(&id) {
+                        h.abort();
+                    }
+                }
+            }
         }
+        queued
     }
 
-    pub async fn run<S: ToSocketAddrs>(&mut self, addr: S) {
+    /// Binds to `addr` and serves sessions until the task is cancelled or the server is quiesced.
+    pub async fn run<S: ToSocketAddrs>(&self, addr: S) {
         let listener = TcpListener::bind(addr).await.expect("failed to bind");
+        self.serve(vec![listener], |socket, addr| {
+            self.spawn_session(Box::new(socket), addr.to_string(), Some(addr))
+        })
+        .await
+    }
+
+    /// Binds to `addr` and serves sessions over TLS until the task is cancelled or the server is quiesced.
+    /// Each handshake runs in its own task, which quiesce waits for like a session, and has to finish within
+    /// the [`auth_timeout`](Self::auth_timeout) if one is set. Connections which fail it are logged and dropped.
+    #[cfg(feature = "tls")]
+    pub async fn run_tls<S: ToSocketAddrs>(&self, addr: S, tls_config: Arc<rustls::ServerConfig>) {
+        let listener = TcpListener::bind(addr).await.expect("failed to bind");
+        let acceptor = TlsAcceptor::from(tls_config);
+        self.serve(vec![listener], |socket, addr| {
+            let acceptor = acceptor.clone();
+            let server = self.clone();
+            let mut shutdown = Some(self.control.shutdown.subscribe());
+            let deadline = self.auth_timeout.map(|t| Instant::now() + t);
+            self.track(
+                async move {
+                    let accepted = tokio::select! {
+                        a = acceptor.accept(socket) => a,
+                        _ = deadline_passed(deadline) => {
+                            warn!("tls handshake with {:?} timed out, dropping it", addr);
+                            return;
+                        }
+                        _ = shutdown_requested(&mut shutdown) => {
+                            debug!("dropping the tls handshake with {:?} for shutdown", addr);
+                            return;
+                        }
+                    };
+                    match accepted {
+                        Ok(stream) => {
+                            debug!("completed a tls handshake with {:?}", addr);
+                            server.spawn_session(Box::new(stream), addr.to_string(), Some(addr));
+                        }
+                        Err(e) => warn!("tls handshake with {:?} failed, dropping it, {}", addr, e),
+                    }
+                },
+                None,
+            );
+        })
+        .await
+    }
+
+    /// Binds every address in `addrs`, such as an IPv4 and an IPv6 address, and serves sessions from all of them
+    /// until the task is cancelled or the server is quiesced. Returns straight away if `addrs` is empty.
+    pub async fn run_multi(&self, addrs: Vec<SocketAddr>) {
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            listeners.push(TcpListener::bind(addr).await.expect("failed to bind"));
+        }
+        self.serve(listeners, |socket, addr| {
+            self.spawn_session(Box::new(socket), addr.to_string(), Some(addr))
+        })
+        .await
+    }
+
+    /// Accepts connections from every listener, passing each configured socket to `on_accept`.
+    async fn serve<F: Fn(TcpStream, SocketAddr)>(&self, listeners: Vec<TcpListener>, on_accept: F) {
+        let mut shutdown = self.control.shutdown.subscribe();
+        let mut accepts = futures::stream::select_all(listeners.into_iter().map(|l| {
+            Box::pin(futures::stream::unfold(l, |l| async {
+                let accepted = l.accept().await;
+                Some((accepted, l))
+            }))
+        }));
 
         loop {
-            let (socket, addr) = match listener.accept().await {
+            let accepted = tokio::select! {
+                a = accepts.next() => match a {
+                    Some(a) => a,
+                    None => return,
+                },
+                _ = shutdown.wait_for(|s| *s) => {
+                    debug!("stopped accepting tcp connections");
+                    return;
+                }
+            };
+            let (socket, addr) = match accepted {
                 Ok(s) => s,
                 Err(e) => {
                     warn!(
@@ -47,86 +522,344 @@ impl<T: RconImpl + std::marker::Send + 'static> RconServer<T> {
                 }
             };
             debug!("A tcp socket was accepted from {:?}", addr);
+            self.configure_socket(&socket, addr);
 
-            let implimentor = T::new(Arc::clone(&self.state));
+            on_accept(socket, addr);
+        }
+    }
+
+    /// Applies the socket options to an accepted connection, logging any the platform rejects.
+    fn configure_socket(&self, socket: &TcpStream, addr: SocketAddr) {
+        if let Err(e) = socket.set_nodelay(true) {
+            warn!("failed to set TCP_NODELAY for {:?}: {}", addr, e);
+        }
+        if let Some(time) = self.tcp_keepalive {
+            if let Err(e) = set_tcp_keepalive(socket, time) {
+                warn!("failed to set SO_KEEPALIVE for {:?}: {}", addr, e);
+            }
+        }
+    }
 
-            let mut serv = ServerSession::from_tcp_stream(socket, implimentor);
+    /// Binds a Unix domain socket at `path` and serves sessions until the task is cancelled or the server is quiesced.
+    /// The socket file is not removed when the server stops.
+    #[cfg(all(unix, feature = "unix"))]
+    pub async fn run_unix<P: AsRef<Path>>(&self, path: P) {
+        let listener = UnixListener::bind(path).expect("failed to bind");
+        let mut shutdown = self.control.shutdown.subscribe();
 
-            let _h = tokio::spawn(async move {
-                let _ = serv
-                    .start()
-                    .map(|x| async {
-                        debug!("completed thread with value {:?}", &x);
-                        x
-                    })
-                    .await;
-            });
+        loop {
+            let accepted = tokio::select! {
+                a = listener.accept() => a,
+                _ = shutdown.wait_for(|s| *s) => {
+                    debug!("stopped accepting unix connections");
+                    return;
+                }
+            };
+            let socket = match accepted {
+                Ok((s, _)) => s,
+                Err(e) => {
+                    warn!(
+                        "an error occured while accepting a unix connection, ignoring, {:?}",
+                        e
+                    );
+                    continue;
+                }
+            };
+            debug!("A unix socket was accepted");
+            self.spawn_session(Box::new(socket), "unix socket".to_owned(), None);
+        }
+    }
+
+    fn spawn_session(&self, stream: Box<dyn Transport>, peer: String, addr: Option<SocketAddr>) {
+        let implimentor = T::new(Arc::clone(&self.state));
+
+        let mut serv = ServerSession::new(stream, peer, implimentor);
+        serv.shutdown = Some(self.control.shutdown.subscribe());
+        serv.peer_addr = addr;
+        serv.on_auth = self.on_auth.clone();
+        serv.on_command = self.on_command.clone();
+        serv.metrics = self.metrics.clone();
+        serv.rate_limit = self
+            .rate_limit
+            .clone()
+            .map(|b| (b, self.rate_limited_body.clone()));
+        serv.auth_banner = self.auth_banner.clone();
+        let (broadcast_tx, broadcast_rx) = mpsc::channel(self.broadcast_buffer);
+        serv.broadcasts = Some(broadcast_rx);
+        serv.auth_timeout = self.auth_timeout;
+        let closed = self.metrics.clone().map(SessionClosed);
+        if let Some(m) = &self.metrics {
+            m.session_opened();
+        }
+
+        self.track(
+            async move {
+                // dropped when the task ends, even if it is aborted
+                let _closed = closed;
+                let x = serv.start().await;
+                debug!("completed thread with value {:?}", &x);
+            },
+            Some(broadcast_tx),
+        );
+    }
+
+    /// Spawns `task` and registers it with the running sessions, so quiesce waits for it or aborts it,
+    /// along with its broadcast queue if it has one.
+    fn track<F>(&self, task: F, broadcasts: Option<mpsc::Sender<String>>)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.control.next_session.fetch_add(1, Ordering::Relaxed);
+        let control = Arc::clone(&self.control);
+        // the lock is held until the handle is registered, so a task that ends straight
+        // away can't try to unregister itself first
+        let mut sessions = self.control.sessions.lock().unwrap();
+        let h = tokio::spawn(async move {
+            task.await;
+            control.sessions.lock().unwrap().remove(&id);
+            control.broadcasts.lock().unwrap().remove(&id);
+        });
+        sessions.insert(id, h);
+        if let Some(tx) = broadcasts {
+            self.control.broadcasts.lock().unwrap().insert(id, tx);
+        }
+    }
+
+    /// Gracefully shuts the server down. The accept loops stop, then every session finishes the
+    /// command it is running, is sent [`SHUTDOWN_NOTICE`] and is closed.
+    /// Sessions still running after `grace` are aborted, returns whether they all closed in time.
+    /// A quiesced server stops straight away if it is run again.
+    pub async fn quiesce(&self, grace: Duration) -> bool {
+        debug!("quiescing server");
+        self.control.shutdown.send_replace(true);
+        let sessions: Vec<_> = self.control.sessions.lock().unwrap().drain().collect();
+        let aborts: Vec<_> = sessions.iter().map(|(_, h)| h.abort_handle()).collect();
+        let finished = timeout(
+            grace,
+            futures::future::join_all(sessions.into_iter().map(|(_, h)| h)),
+        )
+        .await
+        .is_ok();
+        if !finished {
+            warn!("aborting sessions which didn't close within {:?}", grace);
+            for a in aborts {
+                a.abort();
+            }
+            // aborted sessions don't get to unregister themselves
+            self.control.broadcasts.lock().unwrap().clear();
         }
+        finished
+    }
+}
+
+/// Reports a session as closed when dropped.
+struct SessionClosed(Arc<dyn ServerMetrics>);
+
+impl Drop for SessionClosed {
+    fn drop(&mut self) {
+        self.0.session_closed();
     }
 }
 
+/// A single client connection to the server.
 pub struct ServerSession<T: RconImpl> {
-    stream: Framed<TcpStream, PacketCodec>,
+    stream: Framed<Box<dyn Transport>, PacketCodec>,
     authenticated: bool,
     execer: Arc<Mutex<T>>,
+    shutdown: Option<watch::Receiver<bool>>,
+    /// Describes the client for logs
+    peer: String,
+    peer_addr: Option<SocketAddr>,
+    on_auth: Option<AuthHook>,
+    on_command: Option<CommandHook>,
+    metrics: Option<Arc<dyn ServerMetrics>>,
+    /// The session's bucket and the body sent when it is empty
+    rate_limit: Option<(TokenBucket, String)>,
+    auth_banner: Option<String>,
+    broadcasts: Option<mpsc::Receiver<String>>,
+    auth_timeout: Option<Duration>,
+}
+
+/// What a session's loop woke up for.
+enum SessionEvent {
+    Packet(Option<Result<Packet, PacketError>>),
+    Broadcast(String),
+    Shutdown,
+    AuthTimeout,
+}
+
+/// Waits for the server to start quiescing, or forever if the session isn't part of one.
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    match shutdown {
+        Some(s) => {
+            let _ = s.wait_for(|s| *s).await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+/// Waits until `deadline`, or forever if there isn't one.
+async fn deadline_passed(deadline: Option<Instant>) {
+    match deadline {
+        Some(d) => sleep_until(d).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Waits for the next broadcast, or forever if the session doesn't get them.
+async fn next_broadcast(broadcasts: &mut Option<mpsc::Receiver<String>>) -> String {
+    match broadcasts {
+        Some(rx) => match rx.recv().await {
+            Some(body) => body,
+            None => futures::future::pending().await,
+        },
+        None => futures::future::pending().await,
+    }
 }
 
 impl<T: RconImpl> ServerSession<T> {
+    /// Creates a session for an accepted tcp stream.
     pub fn from_tcp_stream(stream: TcpStream, execer: T) -> ServerSession<T> {
+        let addr = stream.peer_addr().ok();
+        let peer = addr.map_or_else(|| "unknown".to_owned(), |a| a.to_string());
+        let mut session = Self::new(Box::new(stream), peer, execer);
+        session.peer_addr = addr;
+        session
+    }
+
+    /// Creates a session over any transport, such as a TLS stream or `tokio::io::duplex` in tests.
+    pub fn from_io<IO: Transport + 'static>(io: IO, execer: T) -> ServerSession<T> {
+        Self::new(Box::new(io), "custom transport".to_owned(), execer)
+    }
+
+    fn new(stream: Box<dyn Transport>, peer: String, execer: T) -> ServerSession<T> {
         let stream = Framed::new(stream, PacketCodec::new_server());
 
         ServerSession {
             stream,
             execer: Arc::new(Mutex::new(execer)),
             authenticated: false,
+            shutdown: None,
+            peer,
+            peer_addr: None,
+            on_auth: None,
+            on_command: None,
+            metrics: None,
+            rate_limit: None,
+            auth_banner: None,
+            broadcasts: None,
+            auth_timeout: None,
         }
     }
 
-    // returns when the session ends
+    /// Serves the session, returning when the client disconnects or is dropped for sending a command before logging in.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "session", skip_all, fields(peer = %self.peer, id = tracing::field::Empty))
+    )]
     pub async fn start(&mut self) -> io::Result<()> {
         debug!("starting client loop");
+        let auth_deadline = self.auth_timeout.map(|t| Instant::now() + t);
         loop {
             let authenticated = self.authenticated;
-            let msg = self.stream.next().await;
+            let deadline = auth_deadline.filter(|_| !authenticated);
+            let event = tokio::select! {
+                m = self.stream.next() => SessionEvent::Packet(m),
+                b = next_broadcast(&mut self.broadcasts) => SessionEvent::Broadcast(b),
+                _ = shutdown_requested(&mut self.shutdown) => SessionEvent::Shutdown,
+                _ = deadline_passed(deadline) => SessionEvent::AuthTimeout,
+            };
+            let msg = match event {
+                SessionEvent::Packet(m) => m,
+                SessionEvent::Broadcast(body) => {
+                    if authenticated {
+                        let pk = Packet {
+                            ptype: PacketType::ResponseValue,
+                            id: 0,
+                            body,
+                        };
+                        if let Err(e) = self.stream.send(pk).await {
+                            debug!("failed to send broadcast, ending session: {}", e);
+                            return Err(e);
+                        }
+                    }
+                    continue;
+                }
+                SessionEvent::Shutdown => return self.shut_down().await,
+                SessionEvent::AuthTimeout => {
+                    debug!(
+                        "{} didn't log in in time, closing the connection",
+                        self.peer
+                    );
+                    return self.stream.close().await;
+                }
+            };
+            #[cfg(feature = "tracing")]
+            if let Some(Ok(p)) = &msg {
+                tracing::Span::current().record("id", p.id);
+            }
             debug!("recieved packet {:?}", msg);
             match msg {
                 Some(Ok(s)) if s.ptype == PacketType::ExecCommand && authenticated => {
+                    if let Some((bucket, body)) = &mut self.rate_limit {
+                        if !bucket.try_take() {
+                            debug!("rate limiting command from {}", self.peer);
+                            let pk = Packet {
+                                ptype: PacketType::ResponseValue,
+                                id: s.id,
+                                body: body.clone(),
+                            };
+                            if let Err(e) = self.stream.send(pk).await {
+                                debug!("failed to send response, ending session: {}", e);
+                                return Err(e);
+                            }
+                            continue;
+                        }
+                    }
+                    if let Some(hook) = &self.on_command {
+                        hook(self.peer_addr, &s.body);
+                    }
                     let mut lock = self.execer.lock().await;
-                    let ret = T::process(&mut *lock, s.body).await;
-                    let pk = Packet {
+                    let body = match T::process(&mut *lock, s.body).await {
+                        Ok(body) => body,
+                        Err(e) => {
+                            debug!("command failed: {}", e);
+                            format!("{}{}", ERROR_PREFIX, e)
+                        }
+                    };
+                    if let Some(m) = &self.metrics {
+                        m.command_processed();
+                    }
+                    let mut pk = Packet {
                         ptype: PacketType::ResponseValue,
                         id: s.id,
-                        body: ret.unwrap(),
+                        body,
                     };
-                    let _ = self.stream.send(pk).await;
+                    // responses aren't split across packets yet, so one which can't be framed is cut short
+                    let max_body = self.stream.codec().max_length().saturating_sub(10);
+                    if pk.body.len() > max_body {
+                        error!(
+                            "truncating a {} byte response to the {} bytes which fit in a packet",
+                            pk.body.len(),
+                            max_body
+                        );
+                        pk = pk.truncated_to(max_body).swap_remove(0);
+                    }
+                    if let Err(e) = self.stream.send(pk).await {
+                        debug!("failed to send response, ending session: {}", e);
+                        return Err(e);
+                    }
                 }
-                Some(Ok(s)) if s.ptype == PacketType::Auth && !authenticated => {
-                    let mut l = self.execer.lock().await;
-                    if T::authenticate(&mut *l, s.body, s.id).await {
-                        debug!("authenticated user");
-                        self.authenticated = true;
-                        let _ = self
-                            .stream
-                            .send(Packet {
-                                id: s.id,
-                                ptype: PacketType::ResponseValue,
-                                body: String::new(),
-                            })
-                            .await;
-                    } else {
-                        debug!("failed to authenticate user");
-                        let _ = self
-                            .stream
-                            .send(Packet {
-                                id: -1,
-                                ptype: PacketType::ResponseValue,
-                                body: String::new(),
-                            })
-                            .await;
+                Some(Ok(s)) if s.ptype == PacketType::Auth => {
+                    if let Err(e) = self.authenticate(s).await {
+                        debug!("failed to send auth response, ending session: {}", e);
+                        return Err(e);
                     }
                 }
                 Some(Ok(s)) if s.ptype == PacketType::ExecCommand && !authenticated => {
-                    warn!("client sending ExecCommand packets without authenticating");
+                    // the spec has the server drop clients which send commands before logging in
+                    warn!("client sent an ExecCommand packet without authenticating, closing the connection");
+                    return self.stream.close().await;
                 }
                 Some(Ok(s)) => {
                     warn!("recieved other packet type: {:?}", s);
@@ -141,4 +874,888 @@ impl<T: RconImpl> ServerSession<T> {
             };
         }
     }
+
+    /// Checks the login and sends the responses, reporting the outcome to the hooks.
+    async fn authenticate(&mut self, s: Packet) -> io::Result<()> {
+        let mut l = self.execer.lock().await;
+        // the spec sends an empty ResponseValue before the AuthResponse
+        self.stream
+            .send(Packet {
+                id: s.id,
+                ptype: PacketType::ResponseValue,
+                body: String::new(),
+            })
+            .await?;
+        let (id, outcome) = if T::authenticate(&mut *l, s.body, s.id).await {
+            debug!("authenticated user");
+            let outcome = if self.authenticated {
+                AuthOutcome::Reauthenticated
+            } else {
+                AuthOutcome::Success
+            };
+            self.authenticated = true;
+            (s.id, outcome)
+        } else {
+            debug!("failed to authenticate user");
+            self.authenticated = false;
+            (-1, AuthOutcome::Failure)
+        };
+        if let (Some(m), AuthOutcome::Failure) = (&self.metrics, outcome) {
+            m.auth_failed();
+        }
+        if let Some(hook) = &self.on_auth {
+            hook(self.peer_addr, outcome);
+        }
+        self.stream
+            .send(Packet {
+                id,
+                ptype: PacketType::AuthResponse,
+                body: String::new(),
+            })
+            .await?;
+        match &self.auth_banner {
+            Some(banner) if outcome != AuthOutcome::Failure => {
+                self.stream
+                    .send(Packet {
+                        id,
+                        ptype: PacketType::ResponseValue,
+                        body: banner.clone(),
+                    })
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn shut_down(&mut self) -> io::Result<()> {
+        debug!("sending shutdown notice");
+        self.stream
+            .send(Packet {
+                ptype: PacketType::ResponseValue,
+                id: 0,
+                body: SHUTDOWN_NOTICE.to_owned(),
+            })
+            .await?;
+        self.stream.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Prefixed<const MAINTENANCE: bool>;
+
+    #[async_trait]
+    impl<const MAINTENANCE: bool> RconImpl for Prefixed<MAINTENANCE> {
+        fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+            Prefixed
+        }
+        async fn authenticate(&mut self, _: String, _: i32) -> bool {
+            !MAINTENANCE
+        }
+        async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+            if MAINTENANCE {
+                Ok(format!("maintenance: {}", cmd))
+            } else {
+                Ok(format!("normal: {}", cmd))
+            }
+        }
+    }
+
+    type Switch = ModeSwitch<Prefixed<false>, Prefixed<true>>;
+
+    /// Answers every command with 10KiB of text.
+    struct Verbose;
+
+    #[async_trait]
+    impl RconImpl for Verbose {
+        fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+            Verbose
+        }
+        async fn authenticate(&mut self, _: String, _: i32) -> bool {
+            true
+        }
+        async fn process(&mut self, _: String) -> Result<String, anyhow::Error> {
+            Ok("\u{e9}".repeat(5 * 1024))
+        }
+    }
+
+    /// Only accepts the password `secret`.
+    struct Secret;
+
+    #[async_trait]
+    impl RconImpl for Secret {
+        fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+            Secret
+        }
+        async fn authenticate(&mut self, password: String, _: i32) -> bool {
+            password == "secret"
+        }
+        async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+            match cmd.as_str() {
+                "fail" => Err(anyhow::anyhow!("no such command")),
+                _ => Ok(cmd),
+            }
+        }
+    }
+
+    /// The shared state key read by [`Greeter`].
+    struct Greeting;
+
+    impl Key for Greeting {
+        type Value = String;
+    }
+
+    /// Responds to every command with the [`Greeting`] in the shared state.
+    struct Greeter {
+        state: Arc<RwLock<ShareMap>>,
+    }
+
+    #[async_trait]
+    impl RconImpl for Greeter {
+        fn new(state: Arc<RwLock<ShareMap>>) -> Self {
+            Greeter { state }
+        }
+        async fn authenticate(&mut self, _: String, _: i32) -> bool {
+            true
+        }
+        async fn process(&mut self, _: String) -> Result<String, anyhow::Error> {
+            let state = self.state.read().await;
+            Ok(state.get::<Greeting>().cloned().unwrap_or_default())
+        }
+    }
+
+    static SLOW_STARTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    /// Takes a while to run commands, so a quiesce can happen in the middle of one.
+    struct Slow;
+
+    #[async_trait]
+    impl RconImpl for Slow {
+        fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+            Slow
+        }
+        async fn authenticate(&mut self, _: String, _: i32) -> bool {
+            true
+        }
+        async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+            SLOW_STARTED.store(true, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(format!("done: {}", cmd))
+        }
+    }
+
+    /// Responds after a short delay, so the client can leave before the response is sent.
+    struct Delayed;
+
+    #[async_trait]
+    impl RconImpl for Delayed {
+        fn new(_: Arc<RwLock<ShareMap>>) -> Self {
+            Delayed
+        }
+        async fn authenticate(&mut self, _: String, _: i32) -> bool {
+            true
+        }
+        async fn process(&mut self, cmd: String) -> Result<String, anyhow::Error> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(cmd)
+        }
+    }
+
+    /// A localhost address with a free port, since the server doesn't report the one it bound.
+    async fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// Connects to `addr`, waiting for a server which is still starting to listen.
+    async fn connect(addr: SocketAddr) -> TcpStream {
+        loop {
+            if let Ok(s) = TcpStream::connect(addr).await {
+                break s;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Connects to `addr` and logs in with `password`, which the server has to accept.
+    async fn connect_and_login(addr: SocketAddr, password: &str) -> Framed<TcpStream, PacketCodec> {
+        let mut c = Framed::new(
+            connect(addr).await,
+            PacketCodec::new(CodecType::Client, 4096),
+        );
+        c.send(Packet {
+            ptype: PacketType::Auth,
+            id: 1,
+            body: password.into(),
+        })
+        .await
+        .unwrap();
+        c.next().await.unwrap().unwrap();
+        assert_eq!(
+            c.next().await.unwrap().unwrap().id,
+            1,
+            "{} rejected the login",
+            addr
+        );
+        c
+    }
+
+    #[tokio::test]
+    async fn mode_switch_routes_by_shared_mode() {
+        let state = Arc::new(RwLock::new(ShareMap::custom()));
+        let mut session = Switch::new(Arc::clone(&state));
+        assert!(session.authenticate(String::new(), 1).await);
+        assert_eq!(
+            session.process("list".into()).await.unwrap(),
+            "normal: list"
+        );
+
+        Switch::set_mode(&state, Mode::Maintenance).await;
+        assert_eq!(
+            session.process("list".into()).await.unwrap(),
+            "maintenance: list"
+        );
+        assert!(
+            !Switch::new(Arc::clone(&state))
+                .authenticate(String::new(), 2)
+                .await
+        );
+
+        Switch::set_mode(&state, Mode::Normal).await;
+        assert_eq!(
+            session.process("list".into()).await.unwrap(),
+            "normal: list"
+        );
+    }
+
+    #[cfg(all(unix, feature = "unix", feature = "client"))]
+    #[tokio::test]
+    async fn round_trips_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("rcon-rs-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = RconServer::<Prefixed<false>>::new();
+        let server_path = path.clone();
+        tokio::spawn(async move { server.run_unix(server_path).await });
+        while !path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let mut c = crate::client::Connection::builder()
+            .connect_unix(&path, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("list").await.unwrap(), "normal: list");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn quiesce_finishes_in_flight_commands_then_notifies() {
+        let addr = free_addr().await;
+        let server = RconServer::<Slow>::new();
+        let running = server.clone();
+        let run = tokio::spawn(async move { running.run(addr).await });
+
+        let mut c = connect_and_login(addr, "password").await;
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 2,
+            body: "save".into(),
+        })
+        .await
+        .unwrap();
+        while !SLOW_STARTED.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+
+        let grace = Duration::from_secs(5);
+        let start = tokio::time::Instant::now();
+        let quiesce = tokio::spawn(async move { server.quiesce(grace).await });
+        let p = c.next().await.unwrap().unwrap();
+        assert_eq!((p.id, p.body.as_str()), (2, "done: save"));
+        let p = c.next().await.unwrap().unwrap();
+        assert_eq!((p.id, p.body.as_str()), (0, SHUTDOWN_NOTICE));
+        assert!(c.next().await.is_none());
+
+        assert!(quiesce.await.unwrap());
+        assert!(start.elapsed() < grace);
+        run.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn on_auth_reports_outcomes() {
+        let addr = free_addr().await;
+        let outcomes = Arc::new(StdMutex::new(Vec::new()));
+        let seen = Arc::clone(&outcomes);
+        let server = RconServer::<Secret>::new()
+            .on_auth(move |peer, outcome| seen.lock().unwrap().push((peer, outcome)));
+        tokio::spawn(async move { server.run(addr).await });
+
+        let socket = connect(addr).await;
+        let local = socket.local_addr().unwrap();
+        let mut c = Framed::new(socket, PacketCodec::new(CodecType::Client, 4096));
+        for (id, password, expected) in [(1, "wrong", -1), (2, "secret", 2), (3, "secret", 3)] {
+            c.send(Packet {
+                ptype: PacketType::Auth,
+                id,
+                body: password.into(),
+            })
+            .await
+            .unwrap();
+            c.next().await.unwrap().unwrap();
+            assert_eq!(c.next().await.unwrap().unwrap().id, expected);
+        }
+
+        assert_eq!(
+            *outcomes.lock().unwrap(),
+            [
+                (Some(local), AuthOutcome::Failure),
+                (Some(local), AuthOutcome::Success),
+                (Some(local), AuthOutcome::Reauthenticated),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_count_a_session() {
+        let addr = free_addr().await;
+        let metrics = Arc::new(CounterMetrics::new());
+        let server = RconServer::<Secret>::new().metrics(metrics.clone());
+        tokio::spawn(async move { server.run(addr).await });
+
+        let mut c = Framed::new(
+            connect(addr).await,
+            PacketCodec::new(CodecType::Client, 4096),
+        );
+        for (id, ptype, body) in [
+            (1, PacketType::Auth, "wrong"),
+            (2, PacketType::Auth, "secret"),
+            (3, PacketType::ExecCommand, "list"),
+            (4, PacketType::ExecCommand, "save"),
+        ] {
+            c.send(Packet {
+                ptype,
+                id,
+                body: body.into(),
+            })
+            .await
+            .unwrap();
+            c.next().await.unwrap().unwrap();
+            if ptype == PacketType::Auth {
+                c.next().await.unwrap().unwrap();
+            }
+        }
+        assert_eq!(metrics.total_sessions(), 1);
+        assert_eq!(metrics.active_sessions(), 1);
+        assert_eq!(metrics.auth_failures(), 1);
+        assert_eq!(metrics.commands_processed(), 2);
+
+        drop(c);
+        while metrics.active_sessions() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(metrics.total_sessions(), 1);
+    }
+
+    #[tokio::test]
+    async fn command_before_auth_closes_connection() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut session = ServerSession::new(
+            Box::new(server),
+            "duplex".to_owned(),
+            Secret::new(Arc::new(RwLock::new(ShareMap::custom()))),
+        );
+        let serve = tokio::spawn(async move { session.start().await });
+
+        let mut c = Framed::new(client, PacketCodec::new(CodecType::Client, 4096));
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 1,
+            body: "list".into(),
+        })
+        .await
+        .unwrap();
+        assert!(c.next().await.is_none());
+        serve.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_ends_when_client_disconnects_mid_response() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut session = ServerSession::new(
+            Box::new(server),
+            "duplex".to_owned(),
+            Delayed::new(Arc::new(RwLock::new(ShareMap::custom()))),
+        );
+        let serve = tokio::spawn(async move { session.start().await });
+
+        let mut c = Framed::new(client, PacketCodec::new(CodecType::Client, 4096));
+        c.send(Packet {
+            ptype: PacketType::Auth,
+            id: 1,
+            body: "password".into(),
+        })
+        .await
+        .unwrap();
+        c.next().await.unwrap().unwrap();
+        c.next().await.unwrap().unwrap();
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 2,
+            body: "save".into(),
+        })
+        .await
+        .unwrap();
+        drop(c);
+
+        let res = timeout(Duration::from_secs(1), serve)
+            .await
+            .expect("session kept running after the client left")
+            .unwrap();
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limit_rejects_commands_over_the_limit() {
+        let addr = free_addr().await;
+        let server = RconServer::<Secret>::new()
+            .rate_limit(3, Duration::from_secs(60))
+            .rate_limited_body("slow down");
+        tokio::spawn(async move { server.run(addr).await });
+
+        let mut c = connect_and_login(addr, "secret").await;
+        for id in 2..7 {
+            c.feed(Packet {
+                ptype: PacketType::ExecCommand,
+                id,
+                body: format!("cmd {}", id),
+            })
+            .await
+            .unwrap();
+        }
+        c.flush().await.unwrap();
+        let mut bodies = Vec::new();
+        for _ in 2..7 {
+            bodies.push(c.next().await.unwrap().unwrap().body);
+        }
+        assert_eq!(
+            bodies,
+            ["cmd 2", "cmd 3", "cmd 4", "slow down", "slow down"]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_multi_accepts_on_every_address() {
+        let mut addrs = vec!["127.0.0.1:0".parse().unwrap()];
+        let v6_available = TcpListener::bind("[::1]:0").await.is_ok();
+        if v6_available {
+            addrs.push("[::1]:0".parse().unwrap());
+        }
+        // pick free ports, like free_addr but on each address
+        for addr in &mut addrs {
+            *addr = TcpListener::bind(*addr)
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+        }
+        let server = RconServer::<Secret>::new();
+        let bound = addrs.clone();
+        tokio::spawn(async move { server.run_multi(bound).await });
+
+        for addr in addrs {
+            connect_and_login(addr, "secret").await;
+        }
+    }
+
+    /// A server config with a self-signed certificate for `localhost`, and a client config which trusts it.
+    #[cfg(feature = "tls")]
+    fn tls_configs() -> (rustls::ServerConfig, rustls::ClientConfig) {
+        use rustls::pki_types::PrivateKeyDer;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key_pair.serialize_der().into()),
+            )
+            .unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        (server_config, client_config)
+    }
+
+    #[cfg(all(feature = "tls", feature = "client"))]
+    #[tokio::test]
+    async fn run_tls_serves_sessions_and_survives_failed_handshakes() {
+        use rustls::pki_types::ServerName;
+        use tokio::io::AsyncWriteExt;
+
+        let (server_config, client_config) = tls_configs();
+        let addr = free_addr().await;
+        let server = RconServer::<Secret>::new();
+        tokio::spawn(async move { server.run_tls(addr, Arc::new(server_config)).await });
+
+        // a plaintext client fails the handshake without stopping the accept loop
+        let mut plain = connect(addr).await;
+        plain.write_all(b"not a tls hello").await.unwrap();
+        drop(plain);
+
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .connect(
+                ServerName::try_from("localhost").unwrap(),
+                connect(addr).await,
+            )
+            .await
+            .unwrap();
+        let mut c = crate::client::Connection::builder()
+            .connect_io(stream, "secret")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("say hello").await.unwrap(), "say hello");
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn stalled_tls_handshakes_time_out_and_are_quiesced() {
+        use tokio::io::AsyncReadExt;
+
+        let (server_config, _) = tls_configs();
+        let addr = free_addr().await;
+        let server = RconServer::<Secret>::new().auth_timeout(Duration::from_millis(50));
+        let running = server.clone();
+        tokio::spawn(async move { running.run_tls(addr, Arc::new(server_config)).await });
+
+        // never starting the handshake gets the connection closed once the timeout passes
+        let mut stalled = connect(addr).await;
+        let mut buf = [0; 16];
+        let read = timeout(Duration::from_secs(5), stalled.read(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), 0);
+
+        // a handshake still running when the server is quiesced is dropped with the sessions
+        let _stalled = connect(addr).await;
+        while server.control.sessions.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert!(server.quiesce(Duration::from_secs(5)).await);
+        assert!(server.control.sessions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn auth_banner_follows_successful_login() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut session = ServerSession::new(
+            Box::new(server),
+            "duplex".to_owned(),
+            Secret::new(Arc::new(RwLock::new(ShareMap::custom()))),
+        );
+        session.auth_banner = Some("welcome".to_owned());
+        tokio::spawn(async move { session.start().await });
+
+        let mut c = Framed::new(client, PacketCodec::new(CodecType::Client, 4096));
+        for (password, id) in [("wrong", 4), ("secret", 5)] {
+            c.send(Packet {
+                ptype: PacketType::Auth,
+                id,
+                body: password.into(),
+            })
+            .await
+            .unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..5 {
+            let p = c.next().await.unwrap().unwrap();
+            received.push((p.ptype, p.id, p.body));
+        }
+        assert_eq!(
+            received,
+            [
+                (PacketType::ResponseValue, 4, String::new()),
+                (PacketType::AuthResponse, -1, String::new()),
+                (PacketType::ResponseValue, 5, String::new()),
+                (PacketType::AuthResponse, 5, String::new()),
+                (PacketType::ResponseValue, 5, "welcome".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn password_auth_rotates_for_new_logins_only() {
+        type Auth = PasswordAuth<Prefixed<false>>;
+        let state = Arc::new(RwLock::new(ShareMap::custom()));
+        Auth::set_password(&state, "old").await;
+        let connect = || {
+            let (client, server) = tokio::io::duplex(4096);
+            let mut session = ServerSession::new(
+                Box::new(server),
+                "duplex".to_owned(),
+                Auth::new(Arc::clone(&state)),
+            );
+            tokio::spawn(async move { session.start().await });
+            Framed::new(client, PacketCodec::new(CodecType::Client, 4096))
+        };
+        async fn login(
+            c: &mut Framed<tokio::io::DuplexStream, PacketCodec>,
+            password: &str,
+        ) -> bool {
+            let auth = Packet {
+                ptype: PacketType::Auth,
+                id: 1,
+                body: password.into(),
+            };
+            c.send(auth).await.unwrap();
+            c.next().await.unwrap().unwrap();
+            c.next().await.unwrap().unwrap().id == 1
+        }
+
+        let mut old = connect();
+        assert!(login(&mut old, "old").await);
+        Auth::set_password(&state, "new").await;
+
+        let cmd = Packet {
+            ptype: PacketType::ExecCommand,
+            id: 2,
+            body: "list".into(),
+        };
+        old.send(cmd).await.unwrap();
+        assert_eq!(old.next().await.unwrap().unwrap().body, "normal: list");
+
+        let mut new = connect();
+        assert!(!login(&mut new, "old").await);
+        assert!(login(&mut new, "new").await);
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_are_truncated_to_fit() {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let mut session = ServerSession::new(
+            Box::new(server),
+            "duplex".to_owned(),
+            Verbose::new(Arc::new(RwLock::new(ShareMap::custom()))),
+        );
+        let serve = tokio::spawn(async move { session.start().await });
+
+        let mut c = Framed::new(client, PacketCodec::new(CodecType::Client, 4096));
+        for (ptype, id) in [(PacketType::Auth, 1), (PacketType::ExecCommand, 2)] {
+            let p = Packet {
+                ptype,
+                id,
+                body: String::new(),
+            };
+            c.send(p).await.unwrap();
+        }
+        for _ in 0..2 {
+            c.next().await.unwrap().unwrap();
+        }
+        let p = c.next().await.unwrap().unwrap();
+        assert_eq!((p.ptype, p.id), (PacketType::ResponseValue, 2));
+        assert_eq!(p.body, "\u{e9}".repeat(2043));
+        drop(c);
+        serve.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn on_command_reports_every_command_with_the_peer() {
+        let addr = free_addr().await;
+        let commands = Arc::new(StdMutex::new(Vec::new()));
+        let seen = Arc::clone(&commands);
+        let server = RconServer::<Secret>::new()
+            .on_command(move |peer, cmd| seen.lock().unwrap().push((peer, cmd.to_owned())));
+        tokio::spawn(async move { server.run(addr).await });
+
+        let mut c = connect_and_login(addr, "secret").await;
+        let local = c.get_ref().local_addr().unwrap();
+        for (id, body) in [(2, "list"), (3, "fail")] {
+            c.send(Packet {
+                ptype: PacketType::ExecCommand,
+                id,
+                body: body.into(),
+            })
+            .await
+            .unwrap();
+            c.next().await.unwrap().unwrap();
+        }
+
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                (Some(local), "list".to_owned()),
+                (Some(local), "fail".to_owned())
+            ]
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn echo_impl_round_trips_with_a_connection() {
+        let state = Arc::new(RwLock::new(ShareMap::custom()));
+        PasswordAuth::<EchoImpl>::set_password(&state, "secret").await;
+        let (client, server) = tokio::io::duplex(4096);
+        let mut session =
+            ServerSession::new(Box::new(server), "duplex".to_owned(), EchoImpl::new(state));
+        tokio::spawn(async move { session.start().await });
+
+        let mut c = crate::client::Connection::from_io(client, "secret")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("say hello").await.unwrap(), "say hello");
+    }
+
+    #[tokio::test]
+    async fn broadcasts_reach_sessions_and_stuck_ones_are_dropped() {
+        let addr = free_addr().await;
+        let server = RconServer::<Secret>::new()
+            .broadcast_buffer(4)
+            .broadcast_overflow(BroadcastOverflow::Disconnect);
+        let running = server.clone();
+        tokio::spawn(async move { running.run(addr).await });
+
+        let mut c = connect_and_login(addr, "secret").await;
+        assert_eq!(server.broadcast("restarting soon"), 1);
+        let p = c.next().await.unwrap().unwrap();
+        assert_eq!((p.id, p.body.as_str()), (0, "restarting soon"));
+
+        // stop reading, until the socket buffers and then the queue fill up
+        let body = "x".repeat(4000);
+        let mut sent = 0;
+        while server.broadcast(&body) == 1 {
+            sent += 1;
+            assert!(sent < 100_000, "the session was never disconnected");
+            tokio::task::yield_now().await;
+        }
+        assert!(server.control.sessions.lock().unwrap().is_empty());
+        assert!(server.control.broadcasts.lock().unwrap().is_empty());
+        assert_eq!(server.broadcast("anyone there?"), 0);
+    }
+
+    #[tokio::test]
+    async fn session_runs_over_an_in_memory_transport() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut session = ServerSession::from_io(
+            server,
+            Secret::new(Arc::new(RwLock::new(ShareMap::custom()))),
+        );
+        let serve = tokio::spawn(async move { session.start().await });
+
+        let mut c = Framed::new(client, PacketCodec::new(CodecType::Client, 4096));
+        for (ptype, id, body) in [
+            (PacketType::Auth, 1, "secret"),
+            (PacketType::ExecCommand, 2, "list"),
+        ] {
+            c.send(Packet {
+                ptype,
+                id,
+                body: body.into(),
+            })
+            .await
+            .unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let p = c.next().await.unwrap().unwrap();
+            received.push((p.ptype, p.id, p.body));
+        }
+        assert_eq!(
+            received,
+            [
+                (PacketType::ResponseValue, 1, String::new()),
+                (PacketType::AuthResponse, 1, String::new()),
+                (PacketType::ResponseValue, 2, "list".to_owned()),
+            ]
+        );
+        drop(c);
+        serve.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_sessions_time_out() {
+        let addr = free_addr().await;
+        let server = RconServer::<Secret>::new().auth_timeout(Duration::from_millis(50));
+        tokio::spawn(async move { server.run(addr).await });
+
+        let start = Instant::now();
+        let mut idle = Framed::new(
+            connect(addr).await,
+            PacketCodec::new(CodecType::Client, 4096),
+        );
+        let closed = timeout(Duration::from_secs(1), idle.next()).await;
+        assert!(
+            matches!(closed, Ok(None)),
+            "the connection should be closed"
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let mut c = connect_and_login(addr, "secret").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 2,
+            body: "list".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(c.next().await.unwrap().unwrap().body, "list");
+    }
+
+    #[tokio::test]
+    async fn sets_tcp_keepalive_on_accepted_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+
+        RconServer::<Secret>::new().configure_socket(&socket, peer);
+        assert!(!socket2::SockRef::from(&socket).keepalive().unwrap());
+        RconServer::<Secret>::new()
+            .tcp_keepalive(Some(Duration::from_secs(30)))
+            .configure_socket(&socket, peer);
+        assert!(socket2::SockRef::from(&socket).keepalive().unwrap());
+        assert!(socket.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn state_is_shared_with_sessions() {
+        let addr = free_addr().await;
+        let server = RconServer::<Greeter>::new();
+        let state = server.state();
+        state.write().await.insert::<Greeting>("hello".to_owned());
+        tokio::spawn(async move { server.run(addr).await });
+
+        let mut c = connect_and_login(addr, "password").await;
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 2,
+            body: "greet".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(c.next().await.unwrap().unwrap().body, "hello");
+
+        state
+            .write()
+            .await
+            .insert::<Greeting>("hi again".to_owned());
+        c.send(Packet {
+            ptype: PacketType::ExecCommand,
+            id: 3,
+            body: "greet".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(c.next().await.unwrap().unwrap().body, "hi again");
+    }
 }
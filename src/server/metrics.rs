@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hooks called by [`RconServer`](super::RconServer) as sessions come and go, for exporting metrics.
+/// Every method does nothing by default.
+pub trait ServerMetrics: Send + Sync {
+    /// A connection was accepted.
+    fn session_opened(&self) {}
+    /// A session ended, whether the client disconnected or it was shut down.
+    fn session_closed(&self) {}
+    /// A command was run, including ones which returned an error.
+    fn command_processed(&self) {}
+    /// A login was rejected.
+    fn auth_failed(&self) {}
+}
+
+/// A [`ServerMetrics`] which counts every event.
+#[derive(Debug, Default)]
+pub struct CounterMetrics {
+    opened: AtomicU64,
+    closed: AtomicU64,
+    commands: AtomicU64,
+    auth_failures: AtomicU64,
+}
+
+impl CounterMetrics {
+    /// Creates a set of counters starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of sessions currently open.
+    pub fn active_sessions(&self) -> u64 {
+        self.opened
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.closed.load(Ordering::Relaxed))
+    }
+
+    /// The number of sessions opened since the counters were created.
+    pub fn total_sessions(&self) -> u64 {
+        self.opened.load(Ordering::Relaxed)
+    }
+
+    /// The number of commands run.
+    pub fn commands_processed(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    /// The number of rejected logins.
+    pub fn auth_failures(&self) -> u64 {
+        self.auth_failures.load(Ordering::Relaxed)
+    }
+}
+
+impl ServerMetrics for CounterMetrics {
+    fn session_opened(&self) {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn session_closed(&self) {
+        self.closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn command_processed(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn auth_failed(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
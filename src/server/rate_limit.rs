@@ -0,0 +1,63 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket holding up to `capacity` commands, refilled evenly over every `interval`.
+#[derive(Debug, Clone)]
+pub(super) struct TokenBucket {
+    capacity: f64,
+    interval: Duration,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub(super) fn new(capacity: u32, interval: Duration) -> Self {
+        TokenBucket {
+            capacity: capacity as f64,
+            interval,
+            tokens: capacity as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a token, returning false if the bucket is empty.
+    pub(super) fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        if !self.interval.is_zero() {
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed / self.interval.as_secs_f64() * self.capacity)
+                .min(self.capacity);
+        } else {
+            self.tokens = self.capacity;
+        }
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_over_the_interval() {
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(1));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+}
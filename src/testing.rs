@@ -0,0 +1,149 @@
+#[cfg(not(feature = "tracing"))]
+use log::{debug, trace};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace};
+
+use super::packet::{CodecType, Packet, PacketCodec, PacketType};
+use futures::{SinkExt, StreamExt};
+use std::{collections::VecDeque, io, net::SocketAddr};
+use tokio::net::TcpListener;
+use tokio_util::codec::Framed;
+
+/// Answers commands the script has no more responses for.
+type CommandHandler = Box<dyn FnMut(&str) -> String + Send>;
+
+/// A scripted rcon server for tests and examples, which accepts a single connection on an ephemeral port.
+/// ```no_run
+/// # async fn f() -> Result<(), rcon_rs::client::Error> {
+/// use rcon_rs::testing::MockServer;
+/// let addr = MockServer::expect_auth("password")
+///     .then_respond("There are 0 of a max of 20 players online")
+///     .on_command(|cmd| format!("unknown command: {}", cmd))
+///     .start()
+///     .await?;
+/// let mut c = rcon_rs::Connection::builder().connect(addr, "password").await?;
+/// println!("{}", c.cmd("list").await?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockServer {
+    password: String,
+    responses: VecDeque<String>,
+    handler: CommandHandler,
+}
+
+impl MockServer {
+    /// Creates a server which only accepts logins with `password`, rejecting others and closing the connection.
+    pub fn expect_auth<P: ToString>(password: P) -> MockServer {
+        MockServer {
+            password: password.to_string(),
+            responses: VecDeque::new(),
+            handler: Box::new(|_| String::new()),
+        }
+    }
+
+    /// Queues `body` as the response to the next command without one, responses are used in the order queued.
+    pub fn then_respond<B: ToString>(mut self, body: B) -> Self {
+        self.responses.push_back(body.to_string());
+        self
+    }
+
+    /// Answers commands with `handler` once the queued responses run out. Defaults to an empty response.
+    pub fn on_command<F: FnMut(&str) -> String + Send + 'static>(mut self, handler: F) -> Self {
+        self.handler = Box::new(handler);
+        self
+    }
+
+    /// Binds to an ephemeral port on localhost and serves the first connection in the background,
+    /// returning the address to connect to.
+    pub async fn start(self) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            match listener.accept().await {
+                Ok((socket, peer)) => {
+                    trace!("mock server accepted {}", peer);
+                    if let Err(e) = self.serve(socket).await {
+                        debug!("mock server connection failed: {}", e);
+                    }
+                }
+                Err(e) => debug!("mock server failed to accept: {}", e),
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn serve(mut self, socket: tokio::net::TcpStream) -> io::Result<()> {
+        let codec = PacketCodec::new(CodecType::Server, 4096);
+        let mut stream = Framed::new(socket, codec);
+        let mut authenticated = false;
+        while let Some(p) = stream.next().await {
+            let p = p.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            match p.ptype {
+                PacketType::Auth => {
+                    authenticated = p.body == self.password;
+                    let id = if authenticated { p.id } else { -1 };
+                    stream
+                        .send(response(PacketType::AuthResponse, id, ""))
+                        .await?;
+                    if !authenticated {
+                        return Ok(());
+                    }
+                }
+                PacketType::ExecCommand if authenticated => {
+                    let body = match self.responses.pop_front() {
+                        Some(body) => body,
+                        None => (self.handler)(&p.body),
+                    };
+                    stream
+                        .send(response(PacketType::ResponseValue, p.id, &body))
+                        .await?;
+                }
+                _ => {
+                    stream
+                        .send(response(PacketType::AuthResponse, -1, ""))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn response(ptype: PacketType, id: i32, body: &str) -> Packet {
+    Packet {
+        ptype,
+        id,
+        body: body.to_owned(),
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::client::{Connection, Error};
+
+    #[tokio::test]
+    async fn scripts_auth_and_commands() {
+        let addr = MockServer::expect_auth("password")
+            .then_respond("first")
+            .on_command(|cmd| format!("echo: {}", cmd))
+            .start()
+            .await
+            .unwrap();
+        let mut c = Connection::builder()
+            .connect(addr, "password")
+            .await
+            .unwrap();
+        assert_eq!(c.cmd("one").await.unwrap(), "first");
+        assert_eq!(c.cmd("two").await.unwrap(), "echo: two");
+    }
+
+    #[tokio::test]
+    async fn rejects_the_wrong_password() {
+        let addr = MockServer::expect_auth("password").start().await.unwrap();
+        let res = Connection::builder().connect(addr, "wrong").await;
+        assert!(matches!(res, Err(Error::AuthFailed)));
+    }
+}
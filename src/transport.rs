@@ -0,0 +1,18 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream a connection or session can run over, implemented for any tokio IO type.
+/// It must be `Sync` so connections can be shared between tasks: handles and persistent connections
+/// hold a `Connection` across awaits in spawned tasks, which requires `&Connection` to be `Send`.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Transport for T {}
+
+/// Turns on SO_KEEPALIVE, sending the first probe once the socket has been idle for `time`.
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn set_tcp_keepalive(
+    stream: &tokio::net::TcpStream,
+    time: std::time::Duration,
+) -> std::io::Result<()> {
+    let keepalive = socket2::TcpKeepalive::new().with_time(time);
+    socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
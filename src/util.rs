@@ -0,0 +1,45 @@
+/// Removes Minecraft's `§` formatting codes: colors `0`-`9` and `a`-`f`, styles `k`-`o` and reset `r`,
+/// in either case. A `§` which isn't followed by one of those is left as it is.
+pub fn strip_minecraft_formatting(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '§' && chars.peek().is_some_and(|&n| is_format_code(n)) {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn is_format_code(c: char) -> bool {
+    matches!(c.to_ascii_lowercase(), '0'..='9' | 'a'..='f' | 'k'..='o' | 'r')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_every_code() {
+        let codes = "0123456789abcdefklmnorABCDEFKLMNOR";
+        let s: String = codes.chars().map(|c| format!("§{}x", c)).collect();
+        assert_eq!(strip_minecraft_formatting(&s), "x".repeat(codes.len()));
+    }
+
+    #[test]
+    fn strips_nested_and_trailing_codes() {
+        assert_eq!(
+            strip_minecraft_formatting("§a§lThere are §c2§r§a players§r"),
+            "There are 2 players"
+        );
+        assert_eq!(strip_minecraft_formatting("§6§"), "§");
+    }
+
+    #[test]
+    fn keeps_unmatched_section_signs() {
+        assert_eq!(strip_minecraft_formatting("§z 5§ §"), "§z 5§ §");
+        assert_eq!(strip_minecraft_formatting("§§a"), "§");
+    }
+}
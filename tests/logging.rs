@@ -0,0 +1,35 @@
+//! Runs in its own process so nothing else in the test suite has installed a logger.
+#![cfg(feature = "client")]
+use rcon_rs::Connection;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn library_does_not_install_a_logger() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let res = Connection::builder()
+        .max_retries(2)
+        .retry_delay(Duration::from_millis(1))
+        .connect(addr, "password")
+        .await;
+    assert!(res.is_err());
+
+    #[cfg(feature = "cli")]
+    rcon_rs::cli::init_logger().expect("a logger was already installed");
+    #[cfg(not(feature = "cli"))]
+    {
+        struct Nop;
+        impl log::Log for Nop {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                false
+            }
+            fn log(&self, _: &log::Record) {}
+            fn flush(&self) {}
+        }
+        log::set_logger(&Nop).expect("a logger was already installed");
+    }
+}